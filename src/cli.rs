@@ -0,0 +1,231 @@
+//! Command-line parsing.
+//!
+//! Accepts the long options below, the forms Windows passes to a screensaver
+//! (`/s`, `/c[:hwnd]` and `/p <hwnd>` or `/p:<hwnd>`) and the ones xscreensaver
+//! passes to a hack (`-root`, `-window-id <id>`).
+
+use std::path::PathBuf;
+
+use crate::config::ViewType;
+
+pub const USAGE: &str = "\
+Usage: rust_flip-rs [options]
+       rust_flip-rs ctl <command>
+
+Options:
+  --screensaver        Run the clock full-screen and exit on input
+  --setup              Open the settings window (default)
+  --windowed           Run the clock in a normal window
+  --desk               Run a desk clock: a window that stays open, Tab switches views
+  --view <view>        Show clock, board, messages or off for this run
+  --monitor <name>     Blank the other monitors (with --view: change only this one)
+  --config <path>      Read and write the config at <path>
+  --safe-mode          Ignore the saved config and don't write it
+  --check-config       Check that the config file loads, then exit
+  -h, --help           Show this help
+
+Windows screensaver forms /s, /c and /p <hwnd> are also accepted, as are the
+xscreensaver hack options -root and -window-id <id> (or $XSCREENSAVER_WINDOW).
+
+The config is --config, else $FLIP_CLOCK_CONFIG, else a config.json next to the
+executable (portable mode), else the one in the per-user config directory.";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Command {
+    #[default]
+    Setup,
+    Screensaver,
+    /// Draw into the screensaver settings preview pane owned by `parent`.
+    Preview { parent: Option<isize> },
+    Windowed,
+    Desk,
+    /// Run as an xscreensaver hack inside `window`, or on the root window.
+    Hack { window: Option<u32> },
+    /// Validate the config file and exit.
+    CheckConfig,
+    /// Send the remaining words to a running clock.
+    Ctl(Vec<String>),
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Cli {
+    pub command: Command,
+    pub view: Option<ViewType>,
+    pub monitor: Option<String>,
+    pub config: Option<PathBuf>,
+    pub safe_mode: bool,
+}
+
+impl Cli {
+    /// Parses the arguments after the program name.
+    pub fn parse<S: AsRef<str>>(args: &[S]) -> Result<Self, String> {
+        let mut cli = Cli::default();
+        let mut command = None;
+        let mut args = args.iter().map(AsRef::as_ref);
+
+        while let Some(arg) = args.next() {
+            // Long options may also be written --name=value
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg, None),
+            };
+            let mut value = |what: &str| {
+                inline.clone()
+                    .or_else(|| args.next().map(str::to_string))
+                    .ok_or_else(|| format!("{} needs {}", name, what))
+            };
+
+            match name {
+                "ctl" => {
+                    set_command(&mut command, Command::Ctl(args.by_ref().map(str::to_string).collect()))?;
+                }
+                "--screensaver" => set_command(&mut command, Command::Screensaver)?,
+                "--setup" => set_command(&mut command, Command::Setup)?,
+                "--windowed" => set_command(&mut command, Command::Windowed)?,
+                "--desk" => set_command(&mut command, Command::Desk)?,
+                "-h" | "--help" | "/?" => set_command(&mut command, Command::Help)?,
+                "--view" => cli.view = Some(value("a view")?.parse()?),
+                "--monitor" => cli.monitor = Some(value("a monitor name")?),
+                "--config" => cli.config = Some(PathBuf::from(value("a path")?)),
+                "--safe-mode" => cli.safe_mode = true,
+                "--check-config" => set_command(&mut command, Command::CheckConfig)?,
+                "-root" => set_command(&mut command, Command::Hack { window: None })?,
+                "-window-id" => {
+                    let id = value("a window id")?;
+                    let window = parse_window_id(&id).ok_or_else(|| format!("Invalid window id '{}'", id))?;
+                    set_command(&mut command, Command::Hack { window: Some(window) })?;
+                }
+                _ if is_windows_flag(arg) => {
+                    let flag = arg[1..2].to_lowercase();
+                    // The HWND follows either after a colon or as the next argument
+                    let hwnd = match arg[2..].strip_prefix(':') {
+                        Some(h) => Some(h.to_string()),
+                        None if arg.len() == 2 && flag == "p" => args.next().map(str::to_string),
+                        None => None,
+                    };
+                    let parent = hwnd
+                        .map(|h| h.trim().parse::<isize>().map_err(|_| format!("Invalid window handle '{}'", h)))
+                        .transpose()?;
+                    match flag.as_str() {
+                        "s" => set_command(&mut command, Command::Screensaver)?,
+                        "c" => set_command(&mut command, Command::Setup)?,
+                        _ => set_command(&mut command, Command::Preview { parent })?,
+                    }
+                }
+                other => return Err(format!("Unknown option '{}'", other)),
+            }
+        }
+
+        if cli.monitor.as_deref() == Some("") {
+            return Err("--monitor needs a monitor name".to_string());
+        }
+        cli.command = command.unwrap_or_default();
+        Ok(cli)
+    }
+}
+
+fn set_command(slot: &mut Option<Command>, cmd: Command) -> Result<(), String> {
+    match (&slot, &cmd) {
+        // `-root -window-id <id>` draws into the window
+        (Some(Command::Hack { window: Some(_) }), Command::Hack { window: None }) => Ok(()),
+        (Some(Command::Hack { window: None }), Command::Hack { .. }) | (None, _) => {
+            *slot = Some(cmd);
+            Ok(())
+        }
+        (Some(existing), _) if *existing != cmd => {
+            Err(format!("Conflicting modes: {:?} and {:?}", existing, cmd))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// X window ids as xscreensaver writes them: `0x1a00003` or decimal.
+pub fn parse_window_id(text: &str) -> Option<u32> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// `/s`, `/S`, `/c:1234`, `/p` ... but not a path like `/home`.
+fn is_windows_flag(arg: &str) -> bool {
+    let mut chars = arg.chars();
+    chars.next() == Some('/')
+        && matches!(chars.next().map(|c| c.to_ascii_lowercase()), Some('s' | 'c' | 'p'))
+        && matches!(chars.next(), None | Some(':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_args_opens_setup() {
+        let empty: [&str; 0] = [];
+        assert_eq!(Cli::parse(&empty), Ok(Cli::default()));
+        assert_eq!(Cli::default().command, Command::Setup);
+    }
+
+    #[test]
+    fn test_windows_screensaver_forms() {
+        assert_eq!(Cli::parse(&["/s"]).unwrap().command, Command::Screensaver);
+        assert_eq!(Cli::parse(&["/S"]).unwrap().command, Command::Screensaver);
+        assert_eq!(Cli::parse(&["/c:4242"]).unwrap().command, Command::Setup);
+        assert_eq!(Cli::parse(&["/p", "1234"]).unwrap().command, Command::Preview { parent: Some(1234) });
+        assert_eq!(Cli::parse(&["/P:98"]).unwrap().command, Command::Preview { parent: Some(98) });
+        assert_eq!(Cli::parse(&["/p"]).unwrap().command, Command::Preview { parent: None });
+        assert!(Cli::parse(&["/p", "window"]).is_err());
+        assert!(Cli::parse(&["/home"]).is_err());
+    }
+
+    #[test]
+    fn test_xscreensaver_hack_forms() {
+        assert_eq!(Cli::parse(&["-root"]).unwrap().command, Command::Hack { window: None });
+        assert_eq!(Cli::parse(&["-window-id", "0x1a00003"]).unwrap().command, Command::Hack { window: Some(0x1a00003) });
+        assert_eq!(Cli::parse(&["-root", "-window-id", "42"]).unwrap().command, Command::Hack { window: Some(42) });
+        assert_eq!(Cli::parse(&["-window-id", "42", "-root"]).unwrap().command, Command::Hack { window: Some(42) });
+        assert!(Cli::parse(&["-window-id", "0xzz"]).is_err());
+        assert!(Cli::parse(&["-root", "--windowed"]).is_err());
+    }
+
+    #[test]
+    fn test_long_options() {
+        let cli = Cli::parse(&[
+            "--screensaver", "--view", "board", "--monitor=HDMI-1", "--config", "/tmp/clock.json", "--safe-mode",
+        ])
+        .unwrap();
+        assert_eq!(cli, Cli {
+            command: Command::Screensaver,
+            view: Some(ViewType::DepartureBoard),
+            monitor: Some("HDMI-1".to_string()),
+            config: Some(PathBuf::from("/tmp/clock.json")),
+            safe_mode: true,
+        });
+
+        assert_eq!(Cli::parse(&["--windowed", "--view=messages"]).unwrap().view, Some(ViewType::MessageBoard));
+        assert_eq!(Cli::parse(&["--desk"]).unwrap().command, Command::Desk);
+        assert_eq!(Cli::parse(&["--check-config", "--config", "a.json"]).unwrap().command, Command::CheckConfig);
+        assert_eq!(Cli::parse(&["-h"]).unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn test_ctl_takes_the_rest() {
+        let cli = Cli::parse(&["--config", "a.json", "ctl", "timer", "start", "--help"]).unwrap();
+        assert_eq!(cli.command, Command::Ctl(vec!["timer".into(), "start".into(), "--help".into()]));
+        assert_eq!(cli.config, Some(PathBuf::from("a.json")));
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(Cli::parse(&["--view"]).is_err());
+        assert!(Cli::parse(&["--view", "tv"]).is_err());
+        assert!(Cli::parse(&["--monitor="]).is_err());
+        assert!(Cli::parse(&["--fullscreen"]).is_err());
+        assert!(Cli::parse(&["--setup", "--windowed"]).is_err());
+        assert!(Cli::parse(&["--desk", "--screensaver"]).is_err());
+        // Repeating the same mode is harmless
+        assert_eq!(Cli::parse(&["/s", "--screensaver"]).unwrap().command, Command::Screensaver);
+    }
+}
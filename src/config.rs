@@ -1,30 +1,314 @@
+use crate::migrations::{self, CURRENT_VERSION};
+use crate::monitor_identity::MonitorIdentity;
+use crate::profiles::{self, Profile, ProfileRule};
 use macroquad::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use directories::ProjectDirs;
+use std::borrow::Cow;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
 pub enum ViewType {
     Clock,
     DepartureBoard,
+    MessageBoard,
+    #[default]
     Off
 }
 
-impl Default for ViewType {
+impl std::str::FromStr for ViewType {
+    type Err = String;
+
+    /// Accepts the short names used on the command line, e.g. `board`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "clock" => Ok(ViewType::Clock),
+            "board" | "departures" | "departureboard" => Ok(ViewType::DepartureBoard),
+            "messages" | "message" | "messageboard" => Ok(ViewType::MessageBoard),
+            "off" => Ok(ViewType::Off),
+            other => Err(format!("Unknown view '{}' (expected clock, board, messages or off)", other)),
+        }
+    }
+}
+
+impl ViewType {
+    /// The next view when cycling through them; `Off` is skipped.
+    pub fn next(&self) -> ViewType {
+        match self {
+            ViewType::Clock => ViewType::DepartureBoard,
+            ViewType::DepartureBoard => ViewType::MessageBoard,
+            ViewType::MessageBoard | ViewType::Off => ViewType::Clock,
+        }
+    }
+}
+
+/// Where a window sits on the desktop, including its frame.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How one monitor differs from the global settings; `None` uses the global value.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MonitorSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_color: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card_color: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_color: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_12h_format: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_seconds: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pixelated: Option<bool>,
+}
+
+impl MonitorSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// One row of the departure board.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BoardCity {
+    pub name: String,
+    /// IANA zone ID, e.g. `America/New_York`.
+    pub zone: String,
+    /// Append the zone's current abbreviation (e.g. EST/EDT) to the name.
+    #[serde(default)]
+    pub show_abbreviation: bool,
+}
+
+impl BoardCity {
+    pub fn new(name: &str, zone: &str, show_abbreviation: bool) -> Self {
+        Self { name: name.to_string(), zone: zone.to_string(), show_abbreviation }
+    }
+}
+
+/// A column of the departure board, drawn right of the city name.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum BoardColumn {
+    Time,
+    AmPm,
+    Weekday,
+    Date,
+    UtcOffset,
+    RelativeOffset,
+}
+
+impl BoardColumn {
+    pub const ALL: [BoardColumn; 6] = [
+        BoardColumn::Time,
+        BoardColumn::AmPm,
+        BoardColumn::Weekday,
+        BoardColumn::Date,
+        BoardColumn::UtcOffset,
+        BoardColumn::RelativeOffset,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BoardColumn::Time => "Time",
+            BoardColumn::AmPm => "AM/PM (12-hour only)",
+            BoardColumn::Weekday => "Weekday",
+            BoardColumn::Date => "Date",
+            BoardColumn::UtcOffset => "UTC Offset",
+            BoardColumn::RelativeOffset => "Offset from Local",
+        }
+    }
+}
+
+/// How the start of a split-flap roll is delayed across the board.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum FlapStagger {
+    None,
+    #[default]
+    Column,
+    Row,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum TextAlign {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+/// One entry in the message board rotation.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BoardMessage {
+    pub text: String,
+    #[serde(default = "default_dwell_secs")]
+    pub dwell_secs: u64, // How long it stays up before the next one
+    #[serde(default)]
+    pub align: TextAlign,
+}
+
+impl BoardMessage {
+    pub fn new(text: &str) -> Self {
+        Self { text: text.to_string(), dwell_secs: default_dwell_secs(), align: TextAlign::default() }
+    }
+}
+
+/// Something a key can do while the clock is showing.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HotkeyAction {
+    Toggle12h,
+    ToggleSeconds,
+    CycleTheme,
+    /// Desk clock: its own view. Otherwise the monitor under the mouse.
+    CycleView,
+    ScaleUp,
+    ScaleDown,
+    /// Desk clock only.
+    ToggleBorderless,
+    /// Desk clock only.
+    ToggleAlwaysOnTop,
+}
+
+impl HotkeyAction {
+    pub const ALL: [HotkeyAction; 8] = [
+        HotkeyAction::Toggle12h,
+        HotkeyAction::ToggleSeconds,
+        HotkeyAction::CycleTheme,
+        HotkeyAction::CycleView,
+        HotkeyAction::ScaleUp,
+        HotkeyAction::ScaleDown,
+        HotkeyAction::ToggleBorderless,
+        HotkeyAction::ToggleAlwaysOnTop,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HotkeyAction::Toggle12h => "Toggle 12/24-Hour",
+            HotkeyAction::ToggleSeconds => "Toggle Seconds",
+            HotkeyAction::CycleTheme => "Next Theme",
+            HotkeyAction::CycleView => "Next View",
+            HotkeyAction::ScaleUp => "Larger",
+            HotkeyAction::ScaleDown => "Smaller",
+            HotkeyAction::ToggleBorderless => "Toggle Window Frame (desk)",
+            HotkeyAction::ToggleAlwaysOnTop => "Toggle Always on Top (desk)",
+        }
+    }
+}
+
+/// A built-in color set.
+pub struct Theme {
+    pub name: &'static str,
+    pub bg: [f32; 3],
+    pub card: [f32; 3],
+    pub text: [f32; 3],
+}
+
+pub const THEMES: [Theme; 5] = [
+    Theme { name: "Graphite", bg: [0.125, 0.125, 0.125], card: [0.165, 0.165, 0.165], text: [0.898, 0.898, 0.898] },
+    Theme { name: "Paper", bg: [0.93, 0.92, 0.89], card: [0.98, 0.98, 0.96], text: [0.15, 0.15, 0.15] },
+    Theme { name: "Solari", bg: [0.02, 0.02, 0.02], card: [0.1, 0.1, 0.1], text: [0.98, 0.8, 0.2] },
+    Theme { name: "Phosphor", bg: [0.0, 0.05, 0.0], card: [0.02, 0.12, 0.03], text: [0.3, 1.0, 0.4] },
+    Theme { name: "Midnight", bg: [0.04, 0.06, 0.12], card: [0.09, 0.12, 0.22], text: [0.75, 0.85, 1.0] },
+];
+
+impl AppConfig {
+    pub fn apply_theme(&mut self, theme: &Theme) {
+        self.bg_color = theme.bg;
+        self.card_color = theme.card;
+        self.text_color = theme.text;
+    }
+
+    /// Switches to the theme after the current one, or the first if the colors are custom.
+    pub fn cycle_theme(&mut self) {
+        let current = THEMES.iter()
+            .position(|t| t.bg == self.bg_color && t.card == self.card_color && t.text == self.text_color);
+        self.apply_theme(&THEMES[current.map_or(0, |i| (i + 1) % THEMES.len())]);
+    }
+
+    /// The settings `monitor` is drawn with: its overrides on top of the global values.
+    pub fn for_monitor(&self, monitor: Option<&str>) -> Cow<'_, AppConfig> {
+        let Some(settings) = monitor.and_then(|m| self.monitor_settings.get(m)).filter(|s| !s.is_empty()) else {
+            return Cow::Borrowed(self);
+        };
+        let mut config = self.clone();
+        config.scale = settings.scale.unwrap_or(self.scale);
+        config.bg_color = settings.bg_color.unwrap_or(self.bg_color);
+        config.card_color = settings.card_color.unwrap_or(self.card_color);
+        config.text_color = settings.text_color.unwrap_or(self.text_color);
+        config.use_12h_format = settings.use_12h_format.unwrap_or(self.use_12h_format);
+        config.show_seconds = settings.show_seconds.unwrap_or(self.show_seconds);
+        config.pixelated = settings.pixelated.unwrap_or(self.pixelated);
+        Cow::Owned(config)
+    }
+}
+
+/// What ends the full-screen screensaver.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExitPolicy {
+    #[serde(default = "default_true")]
+    pub on_key: bool,
+    #[serde(default = "default_true")]
+    pub on_click: bool,
+    #[serde(default = "default_true")]
+    pub on_mouse_move: bool,
+    #[serde(default = "default_true")]
+    pub on_touch: bool,
+    #[serde(default = "default_move_threshold")]
+    pub move_threshold: f32, // px
+    #[serde(default = "default_grace_secs")]
+    pub grace_secs: f64, // Input right after starting is ignored
+    /// Keys held together to exit, e.g. `Ctrl+Shift+Q`. Empty for none.
+    #[serde(default)]
+    pub chord: String,
+    /// Asked for before exiting. Empty for none.
+    #[serde(default)]
+    pub pin: String,
+    /// Ignore keys, clicks, movement and touch; only the chord exits.
+    #[serde(default = "default_false")]
+    pub kiosk: bool,
+}
+
+impl Default for ExitPolicy {
     fn default() -> Self {
-        ViewType::Off
+        Self {
+            on_key: default_true(),
+            on_click: default_true(),
+            on_mouse_move: default_true(),
+            on_touch: default_true(),
+            move_threshold: default_move_threshold(),
+            grace_secs: default_grace_secs(),
+            chord: String::new(),
+            pin: String::new(),
+            kiosk: default_false(),
+        }
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AppConfig {
     #[serde(default)]
-    pub selected_monitor: String, // Legacy support
+    pub version: u32, // Schema version; files without one predate versioning
 
     #[serde(default)]
     pub monitor_views: HashMap<String, ViewType>,
+    #[serde(default)]
+    pub monitor_settings: HashMap<String, MonitorSettings>, // By monitor name, like `monitor_views`
+    #[serde(default)]
+    pub known_monitors: HashMap<String, MonitorIdentity>, // Which monitor each name was last seen on
 
     // General
     #[serde(default = "default_false")]
@@ -51,6 +335,56 @@ pub struct AppConfig {
     pub text_color: [f32; 3],
     #[serde(default = "default_animation_speed")]
     pub animation_speed: u64, // ms
+
+    // Departure Board (rows in display order)
+    #[serde(default = "default_board_cities")]
+    pub board_cities: Vec<BoardCity>,
+    #[serde(default = "default_board_columns")]
+    pub board_columns: Vec<BoardColumn>, // Left to right
+    #[serde(default = "default_false")]
+    pub board_show_seconds: bool,
+
+    // Split-flap animation
+    #[serde(default = "default_true")]
+    pub flap_roll: bool, // Roll through the alphabet instead of a single flip
+    #[serde(default = "default_flap_step")]
+    pub flap_step_ms: u64, // ms per flap
+    #[serde(default)]
+    pub flap_stagger: FlapStagger,
+    #[serde(default = "default_flap_stagger")]
+    pub flap_stagger_ms: u64, // ms per column/row
+
+    // Message Board
+    #[serde(default = "default_messages")]
+    pub messages: Vec<BoardMessage>, // Shown in order, then repeated
+    #[serde(default = "default_message_rows")]
+    pub message_rows: usize,
+    #[serde(default = "default_message_columns")]
+    pub message_columns: usize,
+
+    // Desk Mode
+    #[serde(default = "default_desk_view")]
+    pub desk_view: ViewType,
+    #[serde(default)]
+    pub desk_geometry: Option<WindowGeometry>, // Remembered when the desk clock closes
+    #[serde(default = "default_false")]
+    pub desk_borderless: bool,
+    #[serde(default = "default_false")]
+    pub desk_always_on_top: bool,
+
+    // Screensaver
+    #[serde(default)]
+    pub exit_policy: ExitPolicy,
+
+    // Hotkeys, e.g. `Ctrl+H`; used wherever keys don't close the clock
+    #[serde(default = "default_hotkeys")]
+    pub hotkeys: HashMap<HotkeyAction, String>,
+
+    // Profiles, laid over the settings above while a rule schedules them
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+    #[serde(default)]
+    pub profile_rules: Vec<ProfileRule>, // The first that matches wins
 }
 
 fn default_true() -> bool { true }
@@ -62,12 +396,55 @@ fn default_bg_color() -> [f32; 3] { [0.125, 0.125, 0.125] } // #202020
 fn default_card_color() -> [f32; 3] { [0.165, 0.165, 0.165] } // #2a2a2a
 fn default_text_color() -> [f32; 3] { [0.898, 0.898, 0.898] } // #e5e5e5
 fn default_animation_speed() -> u64 { 600 }
+fn default_board_cities() -> Vec<BoardCity> {
+    vec![
+        BoardCity::new("HAWAII", "Pacific/Honolulu", false),
+        BoardCity::new("LOS ANGELES", "America/Los_Angeles", false),
+        BoardCity::new("NEW YORK", "America/New_York", true),
+        BoardCity::new("UTC", "UTC", false),
+        BoardCity::new("LONDON", "Europe/London", false),
+        BoardCity::new("STOCKHOLM", "Europe/Stockholm", false),
+        BoardCity::new("PARIS", "Europe/Paris", false),
+        BoardCity::new("HANOI", "Asia/Ho_Chi_Minh", false),
+        BoardCity::new("BRISBANE", "Australia/Brisbane", false),
+        BoardCity::new("WELLINGTON", "Pacific/Auckland", false),
+    ]
+}
+fn default_flap_step() -> u64 { 40 }
+fn default_flap_stagger() -> u64 { 15 }
+fn default_dwell_secs() -> u64 { 10 }
+fn default_messages() -> Vec<BoardMessage> { vec![BoardMessage::new("WELCOME")] }
+fn default_message_rows() -> usize { 6 }
+fn default_message_columns() -> usize { 22 }
+fn default_move_threshold() -> f32 { 10.0 }
+fn default_grace_secs() -> f64 { 0.5 }
+fn default_hotkeys() -> HashMap<HotkeyAction, String> {
+    [
+        (HotkeyAction::Toggle12h, "H"),
+        (HotkeyAction::ToggleSeconds, "S"),
+        (HotkeyAction::CycleTheme, "C"),
+        (HotkeyAction::CycleView, "Tab"),
+        (HotkeyAction::ScaleUp, "Equal"),
+        (HotkeyAction::ScaleDown, "Minus"),
+        (HotkeyAction::ToggleBorderless, "B"),
+        (HotkeyAction::ToggleAlwaysOnTop, "T"),
+    ]
+    .into_iter()
+    .map(|(action, keys)| (action, keys.to_string()))
+    .collect()
+}
+fn default_desk_view() -> ViewType { ViewType::Clock }
+fn default_board_columns() -> Vec<BoardColumn> {
+    vec![BoardColumn::Time, BoardColumn::AmPm, BoardColumn::Weekday]
+}
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
-            selected_monitor: String::new(),
+            version: CURRENT_VERSION,
             monitor_views: HashMap::new(),
+            monitor_settings: HashMap::new(),
+            known_monitors: HashMap::new(),
             use_12h_format: default_false(),
             show_seconds: default_true(),
             pixelated: default_false(),
@@ -78,39 +455,600 @@ impl Default for AppConfig {
             card_color: default_card_color(),
             text_color: default_text_color(),
             animation_speed: default_animation_speed(),
+            board_cities: default_board_cities(),
+            board_columns: default_board_columns(),
+            board_show_seconds: default_false(),
+            flap_roll: default_true(),
+            flap_step_ms: default_flap_step(),
+            flap_stagger: FlapStagger::default(),
+            flap_stagger_ms: default_flap_stagger(),
+            messages: default_messages(),
+            message_rows: default_message_rows(),
+            message_columns: default_message_columns(),
+            desk_view: default_desk_view(),
+            desk_geometry: None,
+            desk_borderless: default_false(),
+            desk_always_on_top: default_false(),
+            exit_policy: ExitPolicy::default(),
+            hotkeys: default_hotkeys(),
+            profiles: Vec::new(),
+            profile_rules: Vec::new(),
         }
     }
 }
 
+/// The config file for this run, from `--config` or found on first use, and how it was chosen.
+static CONFIG_PATH: OnceLock<(PathBuf, ConfigSource)> = OnceLock::new();
+/// Set from `--safe-mode`; the saved config is neither read nor written.
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Which rule picked the config file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ConfigSource {
+    Flag,
+    Env,
+    /// Next to the executable.
+    Portable,
+    User,
+    /// The working directory, when nothing else is known.
+    WorkingDir,
+}
+
+pub fn set_config_path(path: PathBuf) {
+    let _ = CONFIG_PATH.set((path, ConfigSource::Flag));
+}
+
+pub fn enable_safe_mode() {
+    SAFE_MODE.store(true, Ordering::SeqCst);
+}
+
+fn config_location() -> &'static (PathBuf, ConfigSource) {
+    CONFIG_PATH.get_or_init(|| {
+        let exe_dir = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf));
+        let user_dir = ProjectDirs::from("com", "rust_flip_rs", "rust_flip_clock").map(|dirs| dirs.config_dir().to_path_buf());
+        find_config_path(std::env::var_os("FLIP_CLOCK_CONFIG"), exe_dir, user_dir)
+    })
+}
+
+/// The config file: `--config`, else `$FLIP_CLOCK_CONFIG`, else a `config.json` next to the
+/// executable (portable mode, e.g. run from a USB stick), else the per-user config directory.
+/// Nothing is created until the config is saved.
 pub fn get_config_path() -> PathBuf {
-    if let Some(proj_dirs) = ProjectDirs::from("com", "rust_flip_rs", "rust_flip_clock") {
-        let config_dir = proj_dirs.config_dir();
-        if !config_dir.exists() {
-            let _ = fs::create_dir_all(config_dir);
+    config_location().0.clone()
+}
+
+/// Whether the config is the one next to the executable.
+pub fn is_portable() -> bool {
+    config_location().1 == ConfigSource::Portable
+}
+
+fn find_config_path(env: Option<std::ffi::OsString>, exe_dir: Option<PathBuf>, user_dir: Option<PathBuf>) -> (PathBuf, ConfigSource) {
+    if let Some(path) = env.filter(|path| !path.is_empty()) {
+        return (PathBuf::from(path), ConfigSource::Env);
+    }
+    let portable = exe_dir.map(|dir| dir.join("config.json"));
+    match (portable, user_dir) {
+        (Some(portable), _) if portable.is_file() => (portable, ConfigSource::Portable),
+        (_, Some(dir)) => (dir.join("config.json"), ConfigSource::User),
+        // Without a home directory, portable is the only place left
+        (Some(portable), None) => (portable, ConfigSource::Portable),
+        (None, None) => (PathBuf::from("config.json"), ConfigSource::WorkingDir),
+    }
+}
+
+/// Why the config file couldn't be used.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, error: io::Error },
+    Write { path: PathBuf, error: io::Error },
+    /// `field` is the JSON path at the error, e.g. `messages[1].dwell_secs`.
+    /// `line` is 0 when the error isn't at a place in the file as written, e.g. in a migrated
+    /// config or a profile that doesn't fit the settings it's laid over.
+    Parse { path: PathBuf, line: usize, column: usize, field: Option<String>, message: String },
+    /// Written by a newer version of the clock, which may use fields this one would drop.
+    TooNew { path: PathBuf, version: u32 },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => write!(f, "Could not read {}: {}", path.display(), error),
+            ConfigError::Write { path, error } => write!(f, "Could not save {}: {}", path.display(), error),
+            ConfigError::Parse { path, line, column, field, message } => {
+                write!(f, "{}", path.display())?;
+                if *line > 0 {
+                    write!(f, " line {}, column {}", line, column)?;
+                }
+                if let Some(field) = field {
+                    write!(f, " (field `{}`)", field)?;
+                }
+                write!(f, ": {}", message)
+            }
+            ConfigError::TooNew { path, version } => write!(
+                f,
+                "{} is config version {}, but this version of the clock only understands up to {}",
+                path.display(),
+                version,
+                CURRENT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// A config that couldn't be loaded and was replaced by the defaults.
+#[derive(Debug)]
+pub struct ConfigReset {
+    pub error: ConfigError,
+    /// Where the unreadable file was moved to, if it was.
+    pub backup: Option<PathBuf>,
+}
+
+/// Reads the config: the user's file laid over the system config. A missing file gives the defaults.
+pub fn load_config() -> Result<AppConfig, ConfigError> {
+    if SAFE_MODE.load(Ordering::SeqCst) {
+        return Ok(AppConfig::default());
+    }
+    load_layered(&SystemConfig::load()?, &get_config_path())
+}
+
+/// Reads one config file on its own, without the system config.
+#[cfg(test)]
+fn load_config_from(path: &Path) -> Result<AppConfig, ConfigError> {
+    load_layered(&SystemConfig::default(), path)
+}
+
+fn load_layered(system: &SystemConfig, path: &Path) -> Result<AppConfig, ConfigError> {
+    let user = read_layer(path)?;
+    if user.is_none() && system.settings.is_empty() {
+        return Ok(AppConfig::default());
+    }
+    let mut config: AppConfig = serde_json::from_value(Value::Object(system.merge(user))).map_err(|e| {
+        ConfigError::Parse { path: path.to_path_buf(), line: 0, column: 0, field: None, message: e.to_string() }
+    })?;
+    // Profiles are laid over the config while they're scheduled, so they can't get around locks either
+    for profile in &mut config.profiles {
+        profile.settings.retain(|key, _| !system.is_locked(key));
+    }
+    profiles::check(&config).map_err(|(field, message)| {
+        ConfigError::Parse { path: path.to_path_buf(), line: 0, column: 0, field: Some(field), message }
+    })?;
+    Ok(config)
+}
+
+/// Reads a config file as JSON, upgraded to `CURRENT_VERSION` if it's older. A missing file is `None`.
+fn read_layer(path: &Path) -> Result<Option<Map<String, Value>>, ConfigError> {
+    read_layer_with(path, &migrations::MIGRATIONS)
+}
+
+fn read_layer_with(path: &Path, steps: &[migrations::Step]) -> Result<Option<Map<String, Value>>, ConfigError> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(ConfigError::Io { path: path.to_path_buf(), error }),
+    };
+    let parse_error = |e: serde_json::Error| {
+        let line = e.line();
+        let column = e.column();
+        let message = e.to_string();
+        // serde_json appends " at line N column M"; we report those separately
+        let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(m, _)| m).to_string();
+        ConfigError::Parse { path: path.to_path_buf(), line, column, field: json_path_at(&content, line, column), message }
+    };
+    let mut value: Value = serde_json::from_str(&content).map_err(parse_error)?;
+    if !value.is_object() {
+        return Err(ConfigError::Parse {
+            path: path.to_path_buf(),
+            line: 1,
+            column: 1,
+            field: None,
+            message: "expected a JSON object".to_string(),
+        });
+    }
+    let latest = steps.len() as u64;
+    match value.get("version").map(Value::as_u64) {
+        Some(Some(version)) if version > latest => {
+            let version = u32::try_from(version).unwrap_or(u32::MAX);
+            return Err(ConfigError::TooNew { path: path.to_path_buf(), version });
+        }
+        // A `version` that isn't a number is left for deserializing to report
+        Some(None) => {}
+        _ => migrations::migrate(&mut value, steps),
+    }
+    // Old files only have to fit the current settings once they're migrated
+    if let Err(e) = AppConfig::deserialize(&value) {
+        // The text as written gives the place in the file, if it fails the same way
+        if let Err(at) = serde_json::from_str::<AppConfig>(&content) {
+            if at.to_string().starts_with(&e.to_string()) {
+                return Err(parse_error(at));
+            }
+        }
+        return Err(ConfigError::Parse { path: path.to_path_buf(), line: 0, column: 0, field: None, message: e.to_string() });
+    }
+    let Value::Object(map) = value else { unreachable!("checked above") };
+    Ok(Some(map))
+}
+
+/// Machine-wide defaults under every user's config, normally set up by an administrator.
+/// `$FLIP_CLOCK_SYSTEM_CONFIG` replaces the platform's location.
+pub fn system_config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("FLIP_CLOCK_SYSTEM_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    #[cfg(windows)]
+    return std::env::var_os("ProgramData").map(|dir| PathBuf::from(dir).join("flip_clock").join("config.json"));
+    #[cfg(not(windows))]
+    Some(PathBuf::from("/etc/flip_clock/config.json"))
+}
+
+/// The system config: settings every user starts from, and under `locked`, the ones they
+/// can't change. Layers are merged by top-level key, so a user's `exit_policy` replaces the
+/// system's whole `exit_policy`.
+#[derive(Debug, Default, Clone)]
+pub struct SystemConfig {
+    pub path: Option<PathBuf>,
+    /// The settings the file sets, and every locked one, written the way `AppConfig` writes them.
+    settings: Map<String, Value>,
+    locked: Vec<String>,
+}
+
+impl SystemConfig {
+    /// Empty in safe mode or when there's no system config file.
+    pub fn load() -> Result<Self, ConfigError> {
+        if SAFE_MODE.load(Ordering::SeqCst) {
+            return Ok(Self::default());
+        }
+        match system_config_path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        let Some(mut file) = read_layer(path)? else { return Ok(Self::default()) };
+        let parse_error = |field: Option<&str>, e: serde_json::Error| ConfigError::Parse {
+            path: path.to_path_buf(),
+            line: 0,
+            column: 0,
+            field: field.map(str::to_string),
+            message: e.to_string(),
+        };
+        let locked: Vec<String> = match file.remove("locked") {
+            Some(locked) => serde_json::from_value(locked).map_err(|e| parse_error(Some("locked"), e))?,
+            None => Vec::new(),
+        };
+        // Round trip through `AppConfig` so values compare equal to the ones saves write
+        let config: AppConfig = serde_json::from_value(Value::Object(file.clone())).map_err(|e| parse_error(None, e))?;
+        let Ok(Value::Object(mut all)) = serde_json::to_value(&config) else { unreachable!("AppConfig is a struct") };
+        for key in &locked {
+            if !all.contains_key(key) {
+                eprintln!("Warning: {} locks `{}`, which isn't a setting", path.display(), key);
+            }
+        }
+        all.retain(|key, _| file.contains_key(key) || locked.contains(key));
+        Ok(Self { path: Some(path.to_path_buf()), settings: all, locked })
+    }
+
+    /// A system config that locks each of `settings` to its value.
+    #[cfg(test)]
+    pub fn locking(settings: Value) -> Self {
+        let Value::Object(settings) = settings else { panic!("settings must be an object") };
+        Self { path: None, locked: settings.keys().cloned().collect(), settings }
+    }
+
+    pub fn is_locked(&self, key: &str) -> bool {
+        self.locked.iter().any(|k| k == key)
+    }
+
+    pub fn has_locks(&self) -> bool {
+        !self.locked.is_empty()
+    }
+
+    /// Lays the user's settings over these, except the locked ones.
+    fn merge(&self, user: Option<Map<String, Value>>) -> Map<String, Value> {
+        let mut merged = self.settings.clone();
+        for (key, value) in user.into_iter().flatten() {
+            if !self.is_locked(&key) {
+                merged.insert(key, value);
+            }
+        }
+        merged
+    }
+
+    /// Puts locked settings back to the values this config gives them.
+    pub fn enforce(&self, config: &mut AppConfig) {
+        if self.locked.is_empty() {
+            return;
+        }
+        let Ok(Value::Object(mut map)) = serde_json::to_value(&*config) else { return };
+        for key in &self.locked {
+            if let Some(value) = self.settings.get(key) {
+                map.insert(key.clone(), value.clone());
+            }
+        }
+        if let Ok(enforced) = serde_json::from_value(Value::Object(map)) {
+            *config = enforced;
+        }
+    }
+
+    /// What the user's file holds for `config`: the settings that differ from these.
+    /// Locked settings are left out, so saving can't change them.
+    fn user_layer(&self, config: &AppConfig) -> Result<String, serde_json::Error> {
+        let Value::Object(mut map) = serde_json::to_value(config)? else { unreachable!("AppConfig is a struct") };
+        map.retain(|key, value| key == "version" || (!self.is_locked(key) && self.settings.get(key) != Some(value)));
+        serde_json::to_string_pretty(&map)
+    }
+}
+
+/// Loads the config for setup, which tells the user why it was reset. One that can't be parsed
+/// is moved to `config.json.bak-<timestamp>` so nothing saved later overwrites it, and the
+/// defaults are used. A config from a newer version stays where it is, and saves are refused
+/// until that version is back. A broken system config is left where it is; saves fail until it's fixed.
+pub fn load_config_or_reset() -> (AppConfig, Option<ConfigReset>) {
+    match load_config() {
+        Ok(config) => (config, None),
+        Err(error) => {
+            eprintln!("Warning: Using the default config. {}", error);
+            (AppConfig::default(), Some(reset_after(error, &get_config_path())))
         }
-        config_dir.join("config.json")
-    } else {
-        PathBuf::from("config.json")
     }
 }
 
-pub fn load_config() -> AppConfig {
-    let path = get_config_path();
-    if let Ok(content) = fs::read_to_string(&path) {
-        if let Ok(config) = serde_json::from_str(&content) {
-            return config;
+fn reset_after(error: ConfigError, user_path: &Path) -> ConfigReset {
+    let backup = match &error {
+        ConfigError::Parse { path, .. } if path == user_path => back_up(path)
+            .map_err(|e| eprintln!("Warning: Could not move {} aside: {}", path.display(), e))
+            .ok(),
+        _ => None,
+    };
+    ConfigReset { error, backup }
+}
+
+fn back_up(path: &Path) -> io::Result<PathBuf> {
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".bak-{}", stamp));
+    let backup = path.with_file_name(name);
+    fs::rename(path, &backup)?;
+    Ok(backup)
+}
+
+/// The JSON path (`exit_policy.grace_secs`, `messages[1].text`) of the value at `line`/`column`,
+/// both 1-based as serde_json reports them.
+fn json_path_at(content: &str, line: usize, column: usize) -> Option<String> {
+    enum Level {
+        Object(Option<String>),
+        Array(usize),
+    }
+
+    let line_start: usize = content.split_inclusive('\n').take(line.saturating_sub(1)).map(str::len).sum();
+    let end = (line_start + column).min(content.len());
+    let mut stack: Vec<Level> = Vec::new();
+    let mut last_string: Option<String> = None;
+    let mut chars = content[..end].chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut text = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => { chars.next(); }
+                        '"' => break,
+                        c => text.push(c),
+                    }
+                }
+                last_string = Some(text);
+            }
+            ':' => {
+                if let Some(Level::Object(key)) = stack.last_mut() {
+                    *key = last_string.take();
+                }
+            }
+            ',' => {
+                match stack.last_mut() {
+                    Some(Level::Array(i)) => *i += 1,
+                    Some(Level::Object(key)) => *key = None,
+                    None => {}
+                }
+            }
+            '{' => stack.push(Level::Object(None)),
+            '[' => stack.push(Level::Array(0)),
+            '}' | ']' => { stack.pop(); }
+            _ => {}
+        }
+    }
+
+    let mut path = String::new();
+    for level in &stack {
+        match level {
+            Level::Object(Some(key)) if path.is_empty() => path.push_str(key),
+            Level::Object(Some(key)) => path.push_str(&format!(".{}", key)),
+            Level::Object(None) => break,
+            Level::Array(i) => path.push_str(&format!("[{}]", i)),
+        }
+    }
+    (!path.is_empty()).then_some(path)
+}
+
+pub fn save_config(config: &AppConfig) -> Result<(), ConfigError> {
+    if SAFE_MODE.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    save_layered(&SystemConfig::load()?, &get_config_path(), config)
+}
+
+fn save_layered(system: &SystemConfig, path: &Path, config: &AppConfig) -> Result<(), ConfigError> {
+    // Never replace a newer version's config with what this one understands
+    if let Err(error @ ConfigError::TooNew { .. }) = read_layer(path) {
+        return Err(error);
+    }
+    if system.settings.is_empty() {
+        return save_config_to(path, config);
+    }
+    let content = system.user_layer(config).map_err(io::Error::other);
+    content
+        .and_then(|content| write_atomically(path, &content))
+        .map_err(|error| ConfigError::Write { path: path.to_path_buf(), error })
+}
+
+pub fn save_config_to(path: &Path, config: &AppConfig) -> Result<(), ConfigError> {
+    let content = serde_json::to_string_pretty(config).map_err(io::Error::other);
+    content
+        .and_then(|content| write_atomically(path, &content))
+        .map_err(|error| ConfigError::Write { path: path.to_path_buf(), error })
+}
+
+/// Writes a temporary file next to `path` and renames it over `path`, so a crash
+/// leaves either the old file or the new one, never half of one.
+fn write_atomically(path: &Path, content: &str) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(format!(".tmp-{}", std::process::id()));
+    let temp = dir.join(temp_name);
+
+    let written = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Ok(dir) = fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Coalesces the stream of changes from setup's sliders into occasional saves.
+///
+/// A save happens once changes pause for `QUIET`, or after `MAX_DELAY` of continuous changes.
+#[derive(Default)]
+pub struct ConfigSaver {
+    first_change: Option<Instant>,
+    last_change: Option<Instant>,
+    /// The most recent save failure, cleared by the next successful save.
+    pub last_error: Option<String>,
+}
+
+impl ConfigSaver {
+    const QUIET: Duration = Duration::from_millis(300);
+    const MAX_DELAY: Duration = Duration::from_secs(2);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn changed(&mut self) {
+        self.changed_at(Instant::now());
+    }
+
+    fn changed_at(&mut self, now: Instant) {
+        self.first_change.get_or_insert(now);
+        self.last_change = Some(now);
+    }
+
+    fn due(&self, now: Instant) -> bool {
+        match (self.first_change, self.last_change) {
+            (Some(first), Some(last)) => now - last >= Self::QUIET || now - first >= Self::MAX_DELAY,
+            _ => false,
+        }
+    }
+
+    /// Called once per frame; saves if a save is due.
+    pub fn poll(&mut self, config: &AppConfig) {
+        if self.due(Instant::now()) {
+            self.flush(config);
         }
     }
-    AppConfig::default()
+
+    /// Saves now if anything changed since the last save.
+    pub fn flush(&mut self, config: &AppConfig) {
+        if self.last_change.is_none() {
+            return;
+        }
+        self.first_change = None;
+        self.last_change = None;
+        self.last_error = save_config(config).err().map(|e| e.to_string());
+    }
 }
 
-pub fn save_config(config: &AppConfig) {
-    let path = get_config_path();
-    if let Ok(content) = serde_json::to_string_pretty(config) {
-        let _ = fs::write(path, content);
+/// Notices edits to the config file, or the system config under it, while the clock runs,
+/// by hand or from another instance.
+///
+/// Each file's modification time and size are checked every `INTERVAL`.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    system_path: Option<PathBuf>,
+    stamps: [Option<(SystemTime, u64)>; 2],
+    last_check: Instant,
+}
+
+impl ConfigWatcher {
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    /// `None` in safe mode, where the files are never read.
+    pub fn new() -> Option<Self> {
+        if SAFE_MODE.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(Self::watching(get_config_path(), system_config_path()))
+    }
+
+    fn watching(path: PathBuf, system_path: Option<PathBuf>) -> Self {
+        let mut watcher = Self { path, system_path, stamps: [None; 2], last_check: Instant::now() };
+        watcher.stamps = watcher.current_stamps();
+        watcher
+    }
+
+    fn current_stamps(&self) -> [Option<(SystemTime, u64)>; 2] {
+        [file_stamp(&self.path), self.system_path.as_deref().and_then(file_stamp)]
+    }
+
+    /// Called once per frame. After a file changes, gives the config they now hold,
+    /// or why it can't be used.
+    pub fn poll(&mut self) -> Option<Result<AppConfig, ConfigError>> {
+        let now = Instant::now();
+        if now - self.last_check < Self::INTERVAL {
+            return None;
+        }
+        self.last_check = now;
+        self.check()
+    }
+
+    fn check(&mut self) -> Option<Result<AppConfig, ConfigError>> {
+        let stamps = self.current_stamps();
+        // A deleted file leaves the running config alone
+        let changed = stamps.iter().zip(&self.stamps).any(|(new, old)| new.is_some() && new != old);
+        self.stamps = stamps;
+        if !changed {
+            return None;
+        }
+        let system = match &self.system_path {
+            Some(path) => SystemConfig::load_from(path),
+            None => Ok(SystemConfig::default()),
+        };
+        Some(system.and_then(|system| load_layered(&system, &self.path)))
     }
 }
 
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,8 +1060,10 @@ mod tests {
         views.insert("Monitor2".to_string(), ViewType::DepartureBoard);
 
         let config = AppConfig {
-            selected_monitor: "TestMonitor".to_string(),
+            version: CURRENT_VERSION,
             monitor_views: views,
+            monitor_settings: HashMap::from([("Monitor2".to_string(), MonitorSettings { scale: Some(0.4), ..MonitorSettings::default() })]),
+            known_monitors: HashMap::new(),
             use_12h_format: true,
             show_seconds: false,
             pixelated: true,
@@ -134,15 +1074,353 @@ mod tests {
             card_color: [0.4, 0.5, 0.6],
             text_color: [0.7, 0.8, 0.9],
             animation_speed: 500,
+            board_cities: vec![BoardCity::new("OSLO", "Europe/Oslo", true)],
+            board_columns: vec![BoardColumn::Date, BoardColumn::Time],
+            board_show_seconds: true,
+            flap_roll: false,
+            flap_step_ms: 80,
+            flap_stagger: FlapStagger::Row,
+            flap_stagger_ms: 25,
+            messages: vec![BoardMessage { text: "LUNCH AT NOON".to_string(), dwell_secs: 30, align: TextAlign::Left }],
+            message_rows: 4,
+            message_columns: 16,
+            desk_view: ViewType::MessageBoard,
+            desk_geometry: Some(WindowGeometry { x: -1200, y: 40, width: 800, height: 300 }),
+            desk_borderless: true,
+            desk_always_on_top: true,
+            exit_policy: ExitPolicy { kiosk: true, chord: "Ctrl+Shift+Q".to_string(), ..ExitPolicy::default() },
+            hotkeys: HashMap::from([(HotkeyAction::CycleTheme, "Ctrl+T".to_string())]),
+            profiles: vec![Profile { name: "Night".to_string(), settings: serde_json::Map::from_iter([("scale".to_string(), 0.5.into())]) }],
+            profile_rules: vec![ProfileRule { profile: "Night".to_string(), days: Vec::new(), start: "22:00".to_string(), end: "06:00".to_string() }],
         };
 
         let json = serde_json::to_string(&config).unwrap();
-        assert!(json.contains("TestMonitor"));
+        assert!(json.contains("\"version\":1"));
         assert!(json.contains("DepartureBoard"));
 
         let loaded: AppConfig = serde_json::from_str(&json).unwrap();
-        assert_eq!(loaded.selected_monitor, "TestMonitor");
-        assert_eq!(loaded.pixelated, true);
+        assert_eq!(loaded.version, CURRENT_VERSION);
+        assert!(loaded.pixelated);
         assert_eq!(loaded.monitor_views.get("Monitor2"), Some(&ViewType::DepartureBoard));
+        assert_eq!(loaded.board_cities, vec![BoardCity::new("OSLO", "Europe/Oslo", true)]);
+        assert_eq!(loaded.board_columns, vec![BoardColumn::Date, BoardColumn::Time]);
+        assert_eq!(loaded.messages[0].align, TextAlign::Left);
+        assert_eq!(loaded.desk_geometry, config.desk_geometry);
+        assert_eq!(loaded.exit_policy, config.exit_policy);
+        assert_eq!(loaded.hotkeys, config.hotkeys);
+        assert_eq!(loaded.monitor_settings, config.monitor_settings);
+        assert_eq!(loaded.profiles, config.profiles);
+        assert_eq!(loaded.profile_rules, config.profile_rules);
+    }
+
+    #[test]
+    fn test_monitor_settings_fall_back_to_global() {
+        let mut config = AppConfig { show_seconds: true, ..AppConfig::default() };
+        config.monitor_settings.insert("PORTRAIT".to_string(), MonitorSettings {
+            scale: Some(0.4),
+            show_seconds: Some(false),
+            text_color: Some([1.0, 0.5, 0.0]),
+            ..MonitorSettings::default()
+        });
+        config.monitor_settings.insert("EMPTY".to_string(), MonitorSettings::default());
+
+        let portrait = config.for_monitor(Some("PORTRAIT"));
+        assert_eq!(portrait.scale, 0.4);
+        assert!(!portrait.show_seconds);
+        assert_eq!(portrait.text_color, [1.0, 0.5, 0.0]);
+        assert_eq!(portrait.bg_color, config.bg_color);
+        assert_eq!(portrait.use_12h_format, config.use_12h_format);
+
+        assert!(matches!(config.for_monitor(Some("EMPTY")), Cow::Borrowed(_)));
+        assert!(matches!(config.for_monitor(Some("MAIN")), Cow::Borrowed(_)));
+        assert!(matches!(config.for_monitor(None), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_missing_board_cities_use_defaults() {
+        let loaded: AppConfig = serde_json::from_str(r#"{ "use_12h_format": true }"#).unwrap();
+        assert_eq!(loaded.board_cities, default_board_cities());
+        assert_eq!(loaded.board_cities[2].zone, "America/New_York");
+        assert_eq!(loaded.exit_policy, ExitPolicy::default());
+        assert_eq!(loaded.hotkeys.len(), HotkeyAction::ALL.len());
+    }
+
+    #[test]
+    fn test_parse_error_names_field_and_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, "{\n  \"scale\": 0.5,\n  \"messages\": [\n    { \"text\": \"HI\" },\n    { \"text\": \"LUNCH\", \"dwell_secs\": \"ten\" }\n  ]\n}\n").unwrap();
+
+        match load_config_from(&path) {
+            Err(ConfigError::Parse { line, field, message, .. }) => {
+                assert_eq!(line, 5);
+                assert_eq!(field.as_deref(), Some("messages[1].dwell_secs"));
+                assert!(message.starts_with("invalid type"), "{}", message);
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+
+        fs::write(&path, "{ \"exit_policy\": { \"kiosk\": true, \"grace_secs\": 1,, } }").unwrap();
+        let error = load_config_from(&path).unwrap_err();
+        assert!(error.to_string().contains("line 1, column 51 (field `exit_policy`)"), "{}", error);
+
+        assert!(load_config_from(&dir.path().join("missing.json")).is_ok());
+    }
+
+    #[test]
+    fn test_legacy_config_is_migrated_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, r#"{ "selected_monitor": "HDMI-1", "scale": 0.7 }"#).unwrap();
+        let loaded = load_config_from(&path).unwrap();
+        assert_eq!(loaded.version, CURRENT_VERSION);
+        assert_eq!(loaded.monitor_views, HashMap::from([("HDMI-1".to_string(), ViewType::Clock)]));
+        assert_eq!(loaded.scale, 0.7);
+
+        fs::write(&path, format!(r#"{{ "version": {} }}"#, CURRENT_VERSION + 1)).unwrap();
+        assert!(matches!(load_config_from(&path), Err(ConfigError::TooNew { .. })));
+    }
+
+    #[test]
+    fn test_config_path_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe_dir = dir.path().join("stick");
+        let user_dir = dir.path().join("home").join("rust_flip_clock");
+        fs::create_dir(&exe_dir).unwrap();
+        let find = |env: Option<&str>| find_config_path(env.map(Into::into), Some(exe_dir.clone()), Some(user_dir.clone()));
+
+        assert_eq!(find(None), (user_dir.join("config.json"), ConfigSource::User));
+        // Looking the path up doesn't create anything
+        assert!(!user_dir.exists());
+
+        fs::write(exe_dir.join("config.json"), "{}").unwrap();
+        assert_eq!(find(None), (exe_dir.join("config.json"), ConfigSource::Portable));
+        assert_eq!(find(Some("/tmp/booth.json")), (PathBuf::from("/tmp/booth.json"), ConfigSource::Env));
+        assert_eq!(find(Some("")), (exe_dir.join("config.json"), ConfigSource::Portable));
+        // Pointing at the portable file by name doesn't make it portable mode
+        let named = exe_dir.join("config.json");
+        assert_eq!(find(named.to_str()), (named, ConfigSource::Env));
+
+        fs::remove_file(exe_dir.join("config.json")).unwrap();
+        assert_eq!(find_config_path(None, Some(exe_dir.clone()), None), (exe_dir.join("config.json"), ConfigSource::Portable));
+        assert_eq!(find_config_path(None, None, None), (PathBuf::from("config.json"), ConfigSource::WorkingDir));
+    }
+
+    #[test]
+    fn test_system_config_is_layered_under_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let system_path = dir.path().join("system.json");
+        let path = dir.path().join("config.json");
+        fs::write(&system_path, r#"{
+            "scale": 0.5,
+            "show_seconds": false,
+            "bg_color": [0.1, 0.2, 0.3],
+            "locked": ["show_seconds", "pixelated"]
+        }"#).unwrap();
+        fs::write(&path, r#"{
+            "bg_color": [1.0, 1.0, 1.0],
+            "show_seconds": true,
+            "pixelated": true,
+            "profiles": [{ "name": "Night", "settings": { "pixelated": true, "scale": 0.4 } }]
+        }"#).unwrap();
+        let system = SystemConfig::load_from(&system_path).unwrap();
+        let loaded = load_layered(&system, &path).unwrap();
+
+        // System defaults fill in, the user's file wins, and locks win over both
+        assert_eq!(loaded.scale, 0.5);
+        assert_eq!(loaded.bg_color, [1.0, 1.0, 1.0]);
+        assert!(!loaded.show_seconds);
+        // Locking a setting the system file doesn't set pins it to the default
+        assert!(!loaded.pixelated);
+        assert_eq!(loaded.profiles[0].settings.keys().collect::<Vec<_>>(), vec!["scale"]);
+
+        // No user file gives the system config
+        let alone = load_layered(&system, &dir.path().join("missing.json")).unwrap();
+        assert_eq!((alone.scale, alone.bg_color), (0.5, [0.1, 0.2, 0.3]));
+        assert_eq!(alone.version, CURRENT_VERSION);
+    }
+
+    #[test]
+    fn test_save_leaves_out_locked_and_system_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let system_path = dir.path().join("system.json");
+        let path = dir.path().join("config.json");
+        fs::write(&system_path, r#"{ "scale": 0.5, "corner_radius": 2.0, "locked": ["corner_radius"] }"#).unwrap();
+        let system = SystemConfig::load_from(&system_path).unwrap();
+
+        let mut config = load_layered(&system, &path).unwrap();
+        config.corner_radius = 12.0;
+        config.spacing = 0.06;
+        save_layered(&system, &path, &config).unwrap();
+
+        let saved: Map<String, Value> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(!saved.contains_key("corner_radius"));
+        // Unchanged settings keep following the system config
+        assert!(!saved.contains_key("scale"));
+        assert!(saved.contains_key("spacing"));
+        assert_eq!(saved["version"], CURRENT_VERSION);
+
+        let reloaded = load_layered(&system, &path).unwrap();
+        assert_eq!((reloaded.corner_radius, reloaded.spacing), (2.0, 0.06));
+
+        system.enforce(&mut config);
+        assert_eq!((config.corner_radius, config.spacing), (2.0, 0.06));
+    }
+
+    #[test]
+    fn test_broken_system_config_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let system_path = dir.path().join("system.json");
+        fs::write(&system_path, r#"{ "locked": "scale" }"#).unwrap();
+        match SystemConfig::load_from(&system_path) {
+            Err(ConfigError::Parse { field, .. }) => assert_eq!(field.as_deref(), Some("locked")),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+        assert!(SystemConfig::load_from(&dir.path().join("missing.json")).unwrap().settings.is_empty());
+    }
+
+    #[test]
+    fn test_migration_runs_before_fields_are_checked() {
+        // A step past today's: `twelve_hour` was renamed, `show_seconds` went from "show"/"hide" to a bool
+        fn v1_renamed(config: &mut Map<String, Value>) {
+            if let Some(value) = config.remove("twelve_hour") {
+                config.insert("use_12h_format".to_string(), value);
+            }
+            if let Some(Value::String(shown)) = config.get("show_seconds") {
+                let shown = shown == "show";
+                config.insert("show_seconds".to_string(), Value::from(shown));
+            }
+        }
+        let steps = [migrations::MIGRATIONS[0], v1_renamed];
+        let path = PathBuf::from(format!("{}/tests/fixtures/config/renamed_field.v1.json", env!("CARGO_MANIFEST_DIR")));
+
+        let layer = read_layer_with(&path, &steps).unwrap().unwrap();
+        let loaded: AppConfig = serde_json::from_value(Value::Object(layer)).unwrap();
+        assert_eq!(loaded.version, 2);
+        assert!(loaded.use_12h_format);
+        assert!(!loaded.show_seconds);
+        assert_eq!(loaded.scale, 0.7);
+
+        // Without the step, the old shape is an error in the file as written
+        match read_layer_with(&path, &migrations::MIGRATIONS) {
+            Err(ConfigError::Parse { line, field, .. }) => {
+                assert_eq!(line, 4);
+                assert_eq!(field.as_deref(), Some("show_seconds"));
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_watcher_reports_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let mut watcher = ConfigWatcher::watching(path.clone(), None);
+        assert!(watcher.check().is_none());
+
+        let edit = |content: &str, secs: u64| {
+            fs::write(&path, content).unwrap();
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+        };
+        edit(r#"{ "scale": 0.5 }"#, 1_000);
+        assert_eq!(watcher.check().unwrap().unwrap().scale, 0.5);
+        assert!(watcher.check().is_none());
+
+        edit(r#"{ "scale": "big" }"#, 2_000);
+        assert!(matches!(watcher.check(), Some(Err(ConfigError::Parse { .. }))));
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.check().is_none());
+    }
+
+    #[test]
+    fn test_broken_config_is_backed_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, "{ \"scale\": ").unwrap();
+        let backup = back_up(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "{ \"scale\": ");
+        assert!(backup.file_name().unwrap().to_str().unwrap().starts_with("config.json.bak-"));
+    }
+
+    #[test]
+    fn test_newer_config_is_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let newer = format!(r#"{{ "version": {}, "scale": 0.5 }}"#, CURRENT_VERSION + 1);
+        fs::write(&path, &newer).unwrap();
+
+        let error = load_config_from(&path).unwrap_err();
+        assert!(reset_after(error, &path).backup.is_none());
+        assert!(matches!(save_layered(&SystemConfig::default(), &path, &AppConfig::default()), Err(ConfigError::TooNew { .. })));
+        assert_eq!(fs::read_to_string(&path).unwrap(), newer);
+
+        // A file that can't be parsed is moved aside instead
+        fs::write(&path, "{ \"scale\": ").unwrap();
+        let error = load_config_from(&path).unwrap_err();
+        assert!(reset_after(error, &path).backup.is_some());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_atomic_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("config.json");
+        let config = AppConfig { scale: 0.5, ..AppConfig::default() };
+        save_config_to(&path, &config).unwrap();
+        save_config_to(&path, &AppConfig { scale: 0.6, ..config }).unwrap();
+
+        assert_eq!(load_config_from(&path).unwrap().scale, 0.6);
+        // Only the config itself is left behind
+        let names: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, vec![std::ffi::OsString::from("config.json")]);
+
+        // A directory in the way fails the save and leaves nothing half-written
+        let blocked = dir.path().join("blocked.json");
+        fs::create_dir(&blocked).unwrap();
+        assert!(matches!(save_config_to(&blocked, &AppConfig::default()), Err(ConfigError::Write { .. })));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_saver_debounce() {
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+        let mut saver = ConfigSaver::new();
+        assert!(!saver.due(ms(1000)));
+
+        // Dragging a slider: a change every 50 ms
+        for t in (0..1000).step_by(50) {
+            saver.changed_at(ms(t));
+            assert!(!saver.due(ms(t + 10)));
+        }
+        assert!(saver.due(ms(950 + 300)));
+
+        // ...but never more than two seconds behind. A fresh saver, as after that save.
+        let mut saver = ConfigSaver::new();
+        for t in (0..2000).step_by(50) {
+            saver.changed_at(ms(t));
+            assert!(!saver.due(ms(t + 10)));
+        }
+        saver.changed_at(ms(2000));
+        assert!(saver.due(ms(2000)));
+    }
+
+    #[test]
+    fn test_cycle_theme() {
+        let mut config = AppConfig::default();
+        config.cycle_theme();
+        assert_eq!(config.text_color, THEMES[1].text);
+        for _ in 1..THEMES.len() {
+            config.cycle_theme();
+        }
+        assert_eq!(config.bg_color, default_bg_color());
+
+        // Custom colors start over at the first theme
+        config.card_color = [1.0, 0.0, 0.0];
+        config.cycle_theme();
+        assert_eq!(config.card_color, THEMES[0].card);
     }
 }
@@ -0,0 +1,474 @@
+//! Local control channel for a running clock.
+//!
+//! `run_clock` serves a Unix domain socket (a named pipe on Windows). Each connection
+//! sends one command line and gets one reply line starting with `OK` or `ERR`, so
+//! scripts can use `flip_clock ctl ...` or anything that can write to a socket.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::ViewType;
+
+pub const USAGE: &str = "\
+Commands:
+  view <monitor> <clock|board|messages|off>   Switch what a monitor shows
+  message <text>                              Show a message on the message board now
+  timer start <duration>                      Count down on the clock, e.g. 90, 5m, 1h30m, 10:00
+  timer stop                                  Go back to showing the time
+  reload                                      Reload the config file";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    SetView { monitor: String, view: ViewType },
+    PushMessage(String),
+    StartTimer(Duration),
+    StopTimer,
+    Reload,
+}
+
+impl ControlCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (verb, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        match verb.to_lowercase().as_str() {
+            "view" => {
+                let (monitor, view) = rest.rsplit_once(char::is_whitespace)
+                    .ok_or("Usage: view <monitor> <clock|board|messages|off>")?;
+                Ok(ControlCommand::SetView {
+                    monitor: monitor.trim().to_string(),
+                    view: view.parse()?,
+                })
+            }
+            "message" if !rest.is_empty() => Ok(ControlCommand::PushMessage(rest.to_string())),
+            "message" => Err("Usage: message <text>".to_string()),
+            "timer" => match rest.split_once(char::is_whitespace).unwrap_or((rest, "")) {
+                ("start", duration) => Ok(ControlCommand::StartTimer(parse_duration(duration)?)),
+                ("stop", "") => Ok(ControlCommand::StopTimer),
+                _ => Err("Usage: timer start <duration> | timer stop".to_string()),
+            },
+            "reload" => Ok(ControlCommand::Reload),
+            "" => Err("Empty command".to_string()),
+            other => Err(format!("Unknown command '{}'", other)),
+        }
+    }
+}
+
+/// The longest timer the clock face can show, 99:59:59.
+pub const MAX_TIMER: Duration = Duration::from_secs(99 * 3600 + 59 * 60 + 59);
+
+/// Parses `90`, `90s`, `5m`, `1h30m`, `10:00` (MM:SS) or `1:00:00` (HH:MM:SS), up to `MAX_TIMER`.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim().to_lowercase();
+    let invalid = || format!("Invalid duration '{}'", text);
+    let too_long = || format!("Duration '{}' is longer than 99:59:59", text);
+
+    let secs: u64 = if text.contains(':') {
+        let parts: Vec<u64> = text.split(':')
+            .map(|p| p.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        if parts.len() > 3 {
+            return Err(invalid());
+        }
+        parts.iter()
+            .try_fold(0u64, |acc, p| acc.checked_mul(60)?.checked_add(*p))
+            .ok_or_else(too_long)?
+    } else if text.chars().all(|c| c.is_ascii_digit()) && !text.is_empty() {
+        // Digits that don't fit a u64 are still a number, just far too long
+        text.parse().map_err(|_| too_long())?
+    } else {
+        let mut total = 0u64;
+        let mut number = String::new();
+        for c in text.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+            let unit = match c {
+                'h' => 3600,
+                'm' => 60,
+                's' => 1,
+                _ => return Err(invalid()),
+            };
+            if number.is_empty() {
+                return Err(invalid());
+            }
+            let n: u64 = number.parse().map_err(|_| too_long())?;
+            total = n.checked_mul(unit)
+                .and_then(|secs| total.checked_add(secs))
+                .ok_or_else(too_long)?;
+            number.clear();
+        }
+        if !number.is_empty() {
+            return Err(invalid());
+        }
+        total
+    };
+
+    if secs == 0 {
+        return Err(invalid());
+    }
+    let duration = Duration::from_secs(secs);
+    if duration > MAX_TIMER {
+        return Err(too_long());
+    }
+    Ok(duration)
+}
+
+/// A command waiting to be applied by the render loop.
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: Sender<Result<String, String>>,
+}
+
+impl ControlRequest {
+    pub fn respond(self, result: Result<String, String>) {
+        let _ = self.reply.send(result);
+    }
+}
+
+fn format_reply(result: Result<String, String>) -> String {
+    match result {
+        Ok(msg) => format!("OK {}", msg).trim_end().to_string(),
+        Err(e) => format!("ERR {}", e),
+    }
+}
+
+/// How long a client has to send its command line.
+#[cfg(unix)]
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads one command from a connection, hands it to the render loop and writes the reply.
+fn handle_connection(stream: impl std::io::Read + Write, requests: &Sender<ControlRequest>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() {
+        return;
+    }
+
+    let result = match ControlCommand::parse(&line) {
+        Ok(command) => {
+            let (reply, replies) = mpsc::channel();
+            if requests.send(ControlRequest { command, reply }).is_err() {
+                return;
+            }
+            replies.recv_timeout(Duration::from_secs(5))
+                .unwrap_or_else(|_| Err("Clock did not respond".to_string()))
+        }
+        Err(e) => Err(e),
+    };
+
+    let mut stream = reader.into_inner();
+    let _ = writeln!(stream, "{}", format_reply(result));
+    let _ = stream.flush();
+}
+
+/// Serves the control channel on a background thread while the clock runs.
+pub struct ControlServer {
+    requests: Receiver<ControlRequest>,
+    stop: Arc<AtomicBool>,
+    path: PathBuf,
+}
+
+impl ControlServer {
+    /// Starts serving at the default endpoint, or returns `None` if another clock already is.
+    pub fn start() -> Option<Self> {
+        Self::start_at(endpoint())
+    }
+
+    /// Applied by the render loop once per frame.
+    pub fn poll(&self) -> Option<ControlRequest> {
+        self.requests.try_recv().ok()
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the listener thread so it notices the stop flag
+        let _ = connect(&self.path);
+        #[cfg(unix)]
+        { let _ = std::fs::remove_file(&self.path); }
+    }
+}
+
+/// Sends one command line to a running clock and returns its reply.
+pub fn send(line: &str) -> Result<String, String> {
+    let path = endpoint();
+    #[cfg(unix)]
+    check_private(&path, false)?;
+    let mut stream = connect(&path)
+        .map_err(|e| format!("No running clock at {} ({})", path.display(), e))?;
+    writeln!(stream, "{}", line).map_err(|e| e.to_string())?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).map_err(|e| e.to_string())?;
+    let reply = reply.trim();
+    match reply.strip_prefix("ERR") {
+        Some(e) => Err(e.trim().to_string()),
+        None => Ok(reply.strip_prefix("OK").unwrap_or(reply).trim().to_string()),
+    }
+}
+
+#[cfg(unix)]
+pub fn endpoint() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => match std::env::var("USER") {
+            Ok(user) if !user.is_empty() => PathBuf::from(dir).join(format!("rust_flip_clock-{}.sock", user)),
+            _ => PathBuf::from(dir).join("rust_flip_clock.sock"),
+        },
+        // Anyone can create files in the temp dir, so the socket goes in a directory only we can use
+        None => std::env::temp_dir()
+            .join(format!("rust_flip_clock-{}", unsafe { libc::geteuid() }))
+            .join("control.sock"),
+    }
+}
+
+/// Makes sure only we can reach the socket's directory, creating it if `create` is set.
+/// A directory someone else owns could hold a socket of theirs posing as the clock.
+#[cfg(unix)]
+fn check_private(path: &std::path::Path, create: bool) -> Result<(), String> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let dir = path.parent().ok_or_else(|| format!("{} has no directory", path.display()))?;
+    if create {
+        match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(format!("Could not create {}: {}", dir.display(), e)),
+        }
+    }
+    let meta = std::fs::symlink_metadata(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    if !meta.is_dir() || meta.uid() != unsafe { libc::geteuid() } || meta.mode() & 0o077 != 0 {
+        return Err(format!("{} is not a private directory of this user", dir.display()));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn connect(path: &std::path::Path) -> std::io::Result<std::os::unix::net::UnixStream> {
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    Ok(stream)
+}
+
+#[cfg(unix)]
+impl ControlServer {
+    pub fn start_at(path: PathBuf) -> Option<Self> {
+        use std::os::unix::net::UnixListener;
+
+        if let Err(e) = check_private(&path, true) {
+            eprintln!("Warning: {}; control disabled", e);
+            return None;
+        }
+        if path.exists() {
+            if connect(&path).is_ok() {
+                eprintln!("Warning: Another clock is serving {}; control disabled", path.display());
+                return None;
+            }
+            // Left over from a crash
+            let _ = std::fs::remove_file(&path);
+        }
+
+        let listener = match UnixListener::bind(&path) {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("Warning: Could not open control socket {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let (tx, requests) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    // A client that connects and never sends a line mustn't hold up the others
+                    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                    let tx = tx.clone();
+                    std::thread::spawn(move || handle_connection(stream, &tx));
+                }
+            }
+        });
+
+        Some(Self { requests, stop, path })
+    }
+}
+
+#[cfg(windows)]
+pub fn endpoint() -> PathBuf {
+    match std::env::var("USERNAME") {
+        Ok(user) if !user.is_empty() => PathBuf::from(format!(r"\\.\pipe\rust_flip_clock-{}", user)),
+        _ => PathBuf::from(r"\\.\pipe\rust_flip_clock"),
+    }
+}
+
+#[cfg(windows)]
+fn connect(path: &std::path::Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new().read(true).write(true).open(path)
+}
+
+#[cfg(windows)]
+impl ControlServer {
+    pub fn start_at(path: PathBuf) -> Option<Self> {
+        use std::ffi::OsStr;
+        use std::os::windows::ffi::OsStrExt;
+        use std::os::windows::io::FromRawHandle;
+        use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+        use winapi::um::errhandlingapi::GetLastError;
+        use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+        use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW};
+        use winapi::um::winbase::{
+            FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+        };
+
+        if connect(&path).is_ok() {
+            eprintln!("Warning: Another clock is serving {}; control disabled", path.display());
+            return None;
+        }
+
+        let wide: Vec<u16> = OsStr::new(&path).encode_wide().chain(std::iter::once(0)).collect();
+        let (tx, requests) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        std::thread::spawn(move || {
+            let mut first = true;
+            while !thread_stop.load(Ordering::SeqCst) {
+                let flags = PIPE_ACCESS_DUPLEX | if first { FILE_FLAG_FIRST_PIPE_INSTANCE } else { 0 };
+                first = false;
+                let pipe = unsafe {
+                    CreateNamedPipeW(wide.as_ptr(), flags, PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+                        PIPE_UNLIMITED_INSTANCES, 4096, 4096, 0, std::ptr::null_mut())
+                };
+                if pipe == INVALID_HANDLE_VALUE {
+                    eprintln!("Warning: Could not open control pipe");
+                    return;
+                }
+                let connected = unsafe { ConnectNamedPipe(pipe, std::ptr::null_mut()) } != 0
+                    || unsafe { GetLastError() } == ERROR_PIPE_CONNECTED;
+                // The File owns the handle and closes it when done
+                let file = unsafe { std::fs::File::from_raw_handle(pipe as _) };
+                if connected && !thread_stop.load(Ordering::SeqCst) {
+                    // A client that connects and never sends a line mustn't hold up the others,
+                    // so the loop goes straight on to the next pipe instance
+                    let tx = tx.clone();
+                    std::thread::spawn(move || {
+                        handle_connection(&file, &tx);
+                        let _ = file.sync_all(); // Wait for the client to read the reply
+                    });
+                }
+            }
+        });
+
+        Some(Self { requests, stop, path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            ControlCommand::parse("view HDMI-1 board"),
+            Ok(ControlCommand::SetView { monitor: "HDMI-1".to_string(), view: ViewType::DepartureBoard })
+        );
+        assert_eq!(
+            ControlCommand::parse(r"view \\.\DISPLAY2 Off"),
+            Ok(ControlCommand::SetView { monitor: r"\\.\DISPLAY2".to_string(), view: ViewType::Off })
+        );
+        assert_eq!(
+            ControlCommand::parse("message  Fire drill at 3pm "),
+            Ok(ControlCommand::PushMessage("Fire drill at 3pm".to_string()))
+        );
+        assert_eq!(ControlCommand::parse("timer start 5m"), Ok(ControlCommand::StartTimer(Duration::from_secs(300))));
+        assert_eq!(ControlCommand::parse("timer stop"), Ok(ControlCommand::StopTimer));
+        assert_eq!(ControlCommand::parse("RELOAD"), Ok(ControlCommand::Reload));
+
+        assert!(ControlCommand::parse("view HDMI-1").is_err());
+        assert!(ControlCommand::parse("view HDMI-1 tv").is_err());
+        assert!(ControlCommand::parse("message").is_err());
+        assert!(ControlCommand::parse("timer pause").is_err());
+        assert!(ControlCommand::parse("dance").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("10:00"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1:00:05"), Ok(Duration::from_secs(3605)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("5x").is_err());
+        assert!(parse_duration("5m3").is_err());
+        assert_eq!(parse_duration("99:59:59"), Ok(MAX_TIMER));
+        assert!(parse_duration("100h").is_err());
+        assert!(parse_duration("99999999999999h").is_err());
+        assert!(parse_duration("99999999999999999999").is_err());
+        assert!(parse_duration("99999999999:0:0").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_round_trip_over_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clock").join("control.sock");
+        let server = ControlServer::start_at(path.clone()).unwrap();
+        // Only one clock may serve the same socket
+        assert!(ControlServer::start_at(path.clone()).is_none());
+        // A client that never sends anything doesn't block the next one
+        let _idle = connect(&path).unwrap();
+
+        let client = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let mut stream = connect(&path).unwrap();
+                writeln!(stream, "timer start 2m").unwrap();
+                let mut reply = String::new();
+                BufReader::new(stream).read_line(&mut reply).unwrap();
+                reply
+            }
+        });
+
+        let request = loop {
+            if let Some(r) = server.poll() {
+                break r;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        assert_eq!(request.command, ControlCommand::StartTimer(Duration::from_secs(120)));
+        request.respond(Ok("Timer started".to_string()));
+        assert_eq!(client.join().unwrap(), "OK Timer started\n");
+
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_dir_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clock").join("control.sock");
+        assert!(check_private(&path, false).is_err());
+        check_private(&path, true).unwrap();
+
+        // A directory others can write to could already hold someone else's socket
+        std::fs::set_permissions(dir.path().join("clock"), std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(check_private(&path, true).is_err());
+        assert!(ControlServer::start_at(path).is_none());
+    }
+}
@@ -0,0 +1,357 @@
+use chrono::{DateTime, Offset, Timelike};
+use chrono_tz::{OffsetName, Tz};
+use std::rc::Rc;
+
+use crate::config::{AppConfig, BoardCity, BoardColumn};
+use crate::split_flap;
+use crate::time_source::TimeSource;
+
+/// Resolves an IANA zone ID, falling back to UTC for unknown IDs.
+pub fn resolve_zone(id: &str) -> Tz {
+    id.parse::<Tz>().unwrap_or_else(|_| {
+        eprintln!("Warning: Unknown time zone '{}', using UTC", id);
+        Tz::UTC
+    })
+}
+
+#[derive(Clone)]
+pub struct DepartureBoardState {
+    // Current display strings per row
+    pub rows: Vec<RowState>,
+    /// Columns the cells were built for, left to right.
+    pub columns: Vec<BoardColumn>,
+    pub show_seconds: bool,
+    last_update: f64,
+    pub time: Rc<dyn TimeSource>,
+}
+
+#[derive(Clone)]
+pub struct RowState {
+    pub city: BoardCity,
+    pub zone: Tz,
+
+    pub label: String,    // City name, plus abbreviation if enabled
+    pub prev_label: String,
+
+    pub cells: Vec<String>, // One per column
+    pub prev_cells: Vec<String>,
+
+    pub anim_start: Option<f64>,
+}
+
+impl DepartureBoardState {
+    pub fn new(time: Rc<dyn TimeSource>, config: &AppConfig) -> Self {
+        let rows = config.board_cities.iter().map(RowState::new).collect();
+        let mut s = Self { rows, columns: Vec::new(), show_seconds: false, last_update: f64::NEG_INFINITY, time };
+        s.update(config); // Initial populate
+        // Set prev = curr to avoid initial flip
+        for row in &mut s.rows {
+            row.prev_label = row.label.clone();
+            row.prev_cells = row.cells.clone();
+            row.anim_start = None;
+        }
+        s
+    }
+
+    /// Rebuilds the rows if the configured city list changed.
+    ///
+    /// Rows for cities that are still present keep their state; new rows flip in from blank.
+    pub fn sync_cities(&mut self, cities: &[BoardCity]) {
+        if self.rows.len() == cities.len() && self.rows.iter().zip(cities).all(|(r, c)| r.city == *c) {
+            return;
+        }
+
+        let mut old_rows = std::mem::take(&mut self.rows);
+        for city in cities {
+            let row = match old_rows.iter().position(|r| r.city == *city) {
+                Some(i) => old_rows.remove(i),
+                None => RowState::new(city),
+            };
+            self.rows.push(row);
+        }
+        self.last_update = f64::NEG_INFINITY;
+    }
+
+    pub fn update(&mut self, config: &AppConfig) {
+        let now_utc = self.time.now();
+
+        // Check if we need to update (every second is fine)
+        let now_secs = self.time.seconds();
+        let format_changed = self.show_seconds != config.board_show_seconds || !self.columns_match(config);
+        if now_secs - self.last_update < 0.1 && !format_changed { return; }
+        self.last_update = now_secs;
+
+        // AM/PM has nothing to say in 24-hour mode
+        self.columns = config.board_columns.iter()
+            .copied()
+            .filter(|c| config.use_12h_format || *c != BoardColumn::AmPm)
+            .collect();
+        self.show_seconds = config.board_show_seconds;
+        let local_offset = self.time.now_local().offset().local_minus_utc();
+
+        for (i, row) in self.rows.iter_mut().enumerate() {
+            let city = &row.city;
+
+            // Calculate time for city; the zone rules decide whether DST applies
+            let city_time = now_utc.with_timezone(&row.zone);
+            let abbreviation = city_time.offset().abbreviation().unwrap_or("");
+
+            let label = if city.show_abbreviation && !abbreviation.is_empty() {
+                format!("{} ({})", city.name, abbreviation)
+            } else {
+                city.name.clone()
+            };
+            let label = split_flap::normalize_str(&label);
+
+            let cells: Vec<String> = self.columns.iter()
+                .map(|c| format_cell(*c, &city_time, local_offset, config.use_12h_format, self.show_seconds))
+                .map(|text| split_flap::normalize_str(&text))
+                .collect();
+
+            if row.label != label || row.cells != cells {
+                // If animation already running, the next one starts from whatever flaps are showing
+                if let Some(start) = row.anim_start {
+                    row.settle_at(config, (now_secs - start) * 1000.0, i);
+                } else {
+                    row.prev_label = row.label.clone();
+                    row.prev_cells = row.cells.clone();
+                }
+                row.label = label;
+                row.cells = cells;
+
+                row.anim_start = Some(now_secs);
+            }
+        }
+    }
+
+    fn columns_match(&self, config: &AppConfig) -> bool {
+        let mut configured = config.board_columns.iter()
+            .filter(|c| config.use_12h_format || **c != BoardColumn::AmPm);
+        self.columns.iter().eq(&mut configured)
+    }
+}
+
+impl RowState {
+    fn new(city: &BoardCity) -> Self {
+        Self {
+            city: city.clone(),
+            zone: resolve_zone(&city.zone),
+            label: String::new(),
+            prev_label: String::new(),
+            cells: Vec::new(),
+            prev_cells: Vec::new(),
+            anim_start: None,
+        }
+    }
+
+    /// Flaps used by the city name; cell flaps are numbered after these for staggering.
+    pub fn label_width(&self) -> usize {
+        self.label.chars().count().max(self.prev_label.chars().count())
+    }
+
+    /// Freezes a running animation: the flaps showing `elapsed_ms` in become the new starting point.
+    fn settle_at(&mut self, config: &AppConfig, elapsed_ms: f64, row_index: usize) {
+        let shown = |from: &str, to: &str, first_column: usize| {
+            split_flap::shown_text(config, from, to, elapsed_ms, row_index, first_column)
+        };
+
+        let mut column = self.label_width();
+        self.prev_label = shown(&self.prev_label, &self.label, 0).trim_end().to_string();
+        self.prev_cells = self.cells.iter().enumerate()
+            .map(|(k, cell)| {
+                let prev = self.prev_cells.get(k).map(String::as_str).unwrap_or("");
+                let text = shown(prev, cell, column);
+                column += cell.chars().count();
+                text
+            })
+            .collect();
+    }
+}
+
+/// Number of flaps a column needs; cells are right-aligned to this width.
+pub fn column_width(column: BoardColumn, show_seconds: bool) -> usize {
+    match column {
+        BoardColumn::Time => if show_seconds { 8 } else { 5 },
+        BoardColumn::AmPm => 2,
+        BoardColumn::Weekday => 3,
+        BoardColumn::Date => 6,
+        BoardColumn::UtcOffset => 6,
+        BoardColumn::RelativeOffset => 6,
+    }
+}
+
+fn format_cell(column: BoardColumn, time: &DateTime<Tz>, local_offset: i32, use_12h: bool, show_seconds: bool) -> String {
+    let text = match column {
+        BoardColumn::Time => {
+            let mut text = if use_12h {
+                format!("{:>2}:{:02}", time.hour12().1, time.minute())
+            } else {
+                format!("{:02}:{:02}", time.hour(), time.minute())
+            };
+            if show_seconds {
+                text += &format!(":{:02}", time.second());
+            }
+            text
+        }
+        BoardColumn::AmPm => if time.hour12().0 { "PM" } else { "AM" }.to_string(),
+        BoardColumn::Weekday => time.format("%a").to_string().to_uppercase(),
+        BoardColumn::Date => time.format("%d %b").to_string().to_uppercase(),
+        BoardColumn::UtcOffset => {
+            let (sign, h, m) = split_offset(time.offset().fix().local_minus_utc());
+            format!("{}{:02}:{:02}", sign, h, m)
+        }
+        BoardColumn::RelativeOffset => {
+            let (sign, h, m) = split_offset(time.offset().fix().local_minus_utc() - local_offset);
+            if m == 0 { format!("{}{}H", sign, h) } else { format!("{}{}:{:02}", sign, h, m) }
+        }
+    };
+    format!("{:>width$}", text, width = column_width(column, show_seconds))
+}
+
+fn split_offset(secs: i32) -> (char, i32, i32) {
+    let sign = if secs < 0 { '-' } else { '+' };
+    let secs = secs.abs();
+    (sign, secs / 3600, (secs % 3600) / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_source::FixedClock;
+
+    fn board_with(rfc3339: &str, config: &AppConfig) -> DepartureBoardState {
+        let clock = FixedClock::new(DateTime::parse_from_rfc3339(rfc3339).unwrap());
+        DepartureBoardState::new(Rc::new(clock), config)
+    }
+
+    fn board_at(rfc3339: &str) -> DepartureBoardState {
+        let config = AppConfig { use_12h_format: true, ..AppConfig::default() };
+        board_with(rfc3339, &config)
+    }
+
+    fn cell<'a>(board: &'a DepartureBoardState, name: &str, column: BoardColumn) -> &'a str {
+        let i = board.columns.iter().position(|c| *c == column).unwrap();
+        &row(board, name).cells[i]
+    }
+
+    fn row<'a>(board: &'a DepartureBoardState, name: &str) -> &'a RowState {
+        board.rows.iter().find(|r| r.city.name == name).unwrap()
+    }
+
+    #[test]
+    fn test_new_york_dst() {
+        let winter = board_at("2024-01-15T17:30:00Z");
+        let ny = row(&winter, "NEW YORK");
+        assert_eq!(ny.label, "NEW YORK (EST)");
+        assert_eq!(ny.cells, vec!["12:30", "PM", "MON"]);
+
+        let summer = board_at("2024-07-15T17:30:00Z");
+        let ny = row(&summer, "NEW YORK");
+        assert_eq!(ny.label, "NEW YORK (EDT)");
+        assert_eq!(ny.cells, vec![" 1:30", "PM", "MON"]);
+    }
+
+    #[test]
+    fn test_european_and_southern_dst() {
+        let summer = board_at("2024-07-15T12:00:00Z");
+        assert_eq!(cell(&summer, "LONDON", BoardColumn::Time), " 1:00");
+        assert_eq!(cell(&summer, "PARIS", BoardColumn::Time), " 2:00");
+        assert_eq!(cell(&summer, "STOCKHOLM", BoardColumn::Time), " 2:00");
+        // Southern hemisphere winter: no DST in Wellington, and none ever in Brisbane
+        assert_eq!(cell(&summer, "WELLINGTON", BoardColumn::Time), "12:00");
+        assert_eq!(cell(&summer, "BRISBANE", BoardColumn::Time), "10:00");
+
+        let winter = board_at("2024-01-15T12:00:00Z");
+        assert_eq!(cell(&winter, "LONDON", BoardColumn::Time), "12:00");
+        assert_eq!(cell(&winter, "LOS ANGELES", BoardColumn::Time), " 4:00");
+        assert_eq!(cell(&winter, "WELLINGTON", BoardColumn::Time), " 1:00");
+        assert_eq!(cell(&winter, "BRISBANE", BoardColumn::Time), "10:00");
+    }
+
+    #[test]
+    fn test_sync_cities_rebuilds_rows() {
+        let mut config = AppConfig { use_12h_format: true, ..AppConfig::default() };
+        let mut board = board_with("2024-07-15T12:00:00Z", &config);
+        let cities = &mut config.board_cities;
+
+        // Reorder, rename and add: untouched rows keep their state, changed ones flip in
+        cities.swap(0, 1);
+        cities[2].name = "NYC".to_string();
+        cities.push(BoardCity::new("TOKYO", "Asia/Tokyo", false));
+        board.sync_cities(cities);
+        board.update(&config);
+
+        let names: Vec<&str> = board.rows.iter().map(|r| r.city.name.as_str()).collect();
+        assert_eq!(&names[..3], &["LOS ANGELES", "HAWAII", "NYC"]);
+        assert_eq!(board.rows.len(), 11);
+        assert!(row(&board, "HAWAII").anim_start.is_none());
+        assert_eq!(row(&board, "NYC").label, "NYC (EDT)");
+        assert!(row(&board, "NYC").anim_start.is_some());
+        assert_eq!(cell(&board, "TOKYO", BoardColumn::Time), " 9:00");
+
+        config.board_cities.retain(|c| c.name != "UTC");
+        board.sync_cities(&config.board_cities);
+        assert_eq!(board.rows.len(), 10);
+    }
+
+    #[test]
+    fn test_columns_follow_config() {
+        let mut config = AppConfig {
+            board_columns: BoardColumn::ALL.to_vec(),
+            board_show_seconds: true,
+            ..AppConfig::default()
+        };
+        // Local time is Paris summer time (+02:00)
+        let clock = Rc::new(FixedClock::new(DateTime::parse_from_rfc3339("2024-07-15T00:05:09+02:00").unwrap()));
+        let mut board = DepartureBoardState::new(clock.clone(), &config);
+
+        // 24-hour mode drops the AM/PM column
+        assert!(!board.columns.contains(&BoardColumn::AmPm));
+        assert_eq!(row(&board, "LONDON").cells, vec!["23:05:09", "SUN", "14 JUL", "+01:00", "   -1H"]);
+        assert_eq!(row(&board, "HANOI").cells, vec!["05:05:09", "MON", "15 JUL", "+07:00", "   +5H"]);
+
+        config.use_12h_format = true;
+        config.board_show_seconds = false;
+        config.board_columns = vec![BoardColumn::Time, BoardColumn::AmPm, BoardColumn::RelativeOffset];
+        config.board_cities.push(BoardCity::new("MUMBAI", "Asia/Kolkata", false));
+        board.sync_cities(&config.board_cities);
+        board.update(&config);
+        assert_eq!(board.columns, config.board_columns);
+        assert_eq!(row(&board, "LONDON").cells, vec!["11:05", "PM", "   -1H"]);
+        assert_eq!(row(&board, "MUMBAI").cells, vec![" 3:35", "AM", " +3:30"]);
+        assert_eq!(row(&board, "HAWAII").cells, vec!["12:05", "PM", "  -12H"]);
+    }
+
+    #[test]
+    fn test_change_mid_roll_starts_from_shown_flaps() {
+        let config = AppConfig {
+            board_cities: vec![BoardCity::new("Malmö", "Europe/Stockholm", false)],
+            board_columns: vec![BoardColumn::Time],
+            board_show_seconds: true,
+            flap_stagger: crate::config::FlapStagger::None,
+            flap_step_ms: 100,
+            ..AppConfig::default()
+        };
+        let clock = Rc::new(FixedClock::new(DateTime::parse_from_rfc3339("2024-07-15T10:01:59+02:00").unwrap()));
+        let mut board = DepartureBoardState::new(clock.clone(), &config);
+        assert_eq!(board.rows[0].label, "MALMO");
+        assert_eq!(board.rows[0].cells, vec!["10:01:59"]);
+
+        clock.advance(chrono::Duration::seconds(1));
+        board.update(&config);
+        assert_eq!(board.rows[0].prev_cells, vec!["10:01:59"]);
+        assert_eq!(board.rows[0].cells, vec!["10:02:00"]);
+
+        // Ten flaps into the long '5' -> '0' and '9' -> '0' rolls the next second arrives
+        clock.advance(chrono::Duration::seconds(1));
+        board.update(&config);
+        assert_eq!(board.rows[0].prev_cells, vec!["10:02:/!"]);
+        assert_eq!(board.rows[0].cells, vec!["10:02:01"]);
+    }
+
+    #[test]
+    fn test_unknown_zone_falls_back_to_utc() {
+        assert_eq!(resolve_zone("Mars/Olympus_Mons"), Tz::UTC);
+        assert_eq!(resolve_zone("Asia/Ho_Chi_Minh"), Tz::Asia__Ho_Chi_Minh);
+    }
+}
@@ -0,0 +1,359 @@
+//! Keyboard, mouse and touch input while the clock is showing.
+//!
+//! Input is read once per frame into an `InputFrame`, so the rules acting on it
+//! can be tested without a window.
+
+use std::collections::{HashMap, HashSet};
+
+use macroquad::prelude::*;
+
+use crate::config::{ExitPolicy, HotkeyAction};
+
+/// How long the PIN prompt waits for the next key before closing.
+const PIN_TIMEOUT_SECS: f64 = 10.0;
+
+/// Keys held together. Each slot accepts any of its keys, so `Ctrl` matches either Control key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyChord(Vec<Vec<KeyCode>>);
+
+impl std::str::FromStr for KeyChord {
+    type Err = String;
+
+    /// Accepts `Ctrl+Shift+Q`, `F11`, `Tab` and so on, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let slots = s.split('+')
+            .map(|name| key_codes(name.trim()).ok_or_else(|| format!("Unknown key '{}'", name.trim())))
+            .collect::<Result<Vec<_>, _>>()?;
+        if s.trim().is_empty() {
+            return Err("No keys given".to_string());
+        }
+        Ok(KeyChord(slots))
+    }
+}
+
+impl KeyChord {
+    /// Whether the chord completes this frame: every key held, at least one just pressed,
+    /// and no modifier held that the chord doesn't name (so `S` doesn't fire on `Ctrl+S`).
+    pub fn pressed(&self, frame: &InputFrame) -> bool {
+        let is_down = |k: &KeyCode| frame.down.contains(k) || frame.pressed.contains(k);
+        let held = |slot: &Vec<KeyCode>| slot.iter().any(is_down);
+        let just = |slot: &Vec<KeyCode>| slot.iter().any(|k| frame.pressed.contains(k));
+        let extra_modifier = MODIFIERS.iter()
+            .any(|m| is_down(m) && !self.0.iter().any(|slot| slot.contains(m)));
+        self.0.iter().all(held) && self.0.iter().any(just) && !extra_modifier
+    }
+}
+
+const MODIFIERS: [KeyCode; 8] = [
+    KeyCode::LeftControl, KeyCode::RightControl, KeyCode::LeftShift, KeyCode::RightShift,
+    KeyCode::LeftAlt, KeyCode::RightAlt, KeyCode::LeftSuper, KeyCode::RightSuper,
+];
+
+const LETTERS: [KeyCode; 26] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G, KeyCode::H, KeyCode::I,
+    KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N, KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R,
+    KeyCode::S, KeyCode::T, KeyCode::U, KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+];
+const DIGITS: [KeyCode; 10] = [
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+];
+const FUNCTION_KEYS: [KeyCode; 12] = [
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+];
+
+fn key_codes(name: &str) -> Option<Vec<KeyCode>> {
+    let name = name.to_lowercase();
+    let key = match name.as_str() {
+        "ctrl" | "control" => return Some(vec![KeyCode::LeftControl, KeyCode::RightControl]),
+        "shift" => return Some(vec![KeyCode::LeftShift, KeyCode::RightShift]),
+        "alt" => return Some(vec![KeyCode::LeftAlt, KeyCode::RightAlt]),
+        "super" | "win" | "meta" | "cmd" => return Some(vec![KeyCode::LeftSuper, KeyCode::RightSuper]),
+        "space" => KeyCode::Space,
+        "enter" | "return" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Escape,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" | "ins" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" | "pgup" => KeyCode::PageUp,
+        "pagedown" | "pgdn" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "minus" | "-" => KeyCode::Minus,
+        "equal" | "=" | "plus" => KeyCode::Equal,
+        "comma" | "," => KeyCode::Comma,
+        "period" | "." => KeyCode::Period,
+        "slash" | "/" => KeyCode::Slash,
+        other => {
+            let mut chars = other.chars();
+            match (chars.next(), chars.as_str()) {
+                (Some(c @ 'a'..='z'), "") => LETTERS[c as usize - 'a' as usize],
+                (Some(c @ '0'..='9'), "") => DIGITS[c as usize - '0' as usize],
+                (Some('f'), n) => *FUNCTION_KEYS.get(n.parse::<usize>().ok()?.checked_sub(1)?)?,
+                _ => return None,
+            }
+        }
+    };
+    Some(vec![key])
+}
+
+/// Everything the clock reacts to, read once per frame.
+#[derive(Clone, Debug, Default)]
+pub struct InputFrame {
+    pub time: f64,
+    /// Keys pressed since the last frame.
+    pub pressed: HashSet<KeyCode>,
+    pub down: HashSet<KeyCode>,
+    /// Text typed since the last frame.
+    pub chars: Vec<char>,
+    pub clicked: bool,
+    pub mouse: (f32, f32),
+    pub touched: bool,
+}
+
+impl InputFrame {
+    pub fn capture() -> Self {
+        let mut chars = Vec::new();
+        while let Some(c) = get_char_pressed() {
+            chars.push(c);
+        }
+        Self {
+            time: get_time(),
+            pressed: get_keys_pressed(),
+            down: get_keys_down(),
+            chars,
+            clicked: [MouseButton::Left, MouseButton::Right, MouseButton::Middle].into_iter().any(is_mouse_button_pressed),
+            mouse: mouse_position(),
+            touched: touches().iter().any(|t| t.phase == TouchPhase::Started),
+        }
+    }
+}
+
+/// The configured hotkeys, parsed once.
+pub struct Hotkeys(Vec<(HotkeyAction, KeyChord)>);
+
+impl Hotkeys {
+    /// Bindings that don't parse are skipped with a warning.
+    pub fn new(bindings: &HashMap<HotkeyAction, String>) -> Self {
+        let mut parsed = Vec::new();
+        for action in HotkeyAction::ALL {
+            let Some(keys) = bindings.get(&action).map(|k| k.trim()).filter(|k| !k.is_empty()) else { continue };
+            match keys.parse() {
+                Ok(chord) => parsed.push((action, chord)),
+                Err(e) => eprintln!("Warning: Ignoring hotkey '{}' for {:?}: {}", keys, action, e),
+            }
+        }
+        Self(parsed)
+    }
+
+    /// Actions whose keys were pressed this frame, in `HotkeyAction::ALL` order.
+    pub fn pressed(&self, frame: &InputFrame) -> Vec<HotkeyAction> {
+        self.0.iter().filter(|(_, chord)| chord.pressed(frame)).map(|(action, _)| *action).collect()
+    }
+}
+
+struct PinEntry {
+    typed: String,
+    last_input: f64,
+    wrong: bool,
+}
+
+/// Applies an `ExitPolicy` to the screensaver's input, frame by frame.
+pub struct ExitWatcher {
+    policy: ExitPolicy,
+    chord: Option<KeyChord>,
+    start: f64,
+    /// Where the mouse rested once the grace period ended.
+    mouse_origin: Option<(f32, f32)>,
+    pin_entry: Option<PinEntry>,
+}
+
+impl ExitWatcher {
+    pub fn new(policy: &ExitPolicy, start: f64) -> Self {
+        let chord = match policy.chord.trim() {
+            "" => None,
+            text => text.parse()
+                .map_err(|e| eprintln!("Warning: Ignoring exit chord '{}': {}", text, e))
+                .ok(),
+        };
+        Self { policy: policy.clone(), chord, start, mouse_origin: None, pin_entry: None }
+    }
+
+    pub fn policy(&self) -> &ExitPolicy {
+        &self.policy
+    }
+
+    /// True once the screensaver should close.
+    pub fn update(&mut self, frame: &InputFrame) -> bool {
+        if self.pin_entry.is_some() {
+            return self.update_pin(frame);
+        }
+        if frame.time - self.start < self.policy.grace_secs {
+            return false;
+        }
+
+        let origin = *self.mouse_origin.get_or_insert(frame.mouse);
+        let moved = (frame.mouse.0 - origin.0).abs() > self.policy.move_threshold
+            || (frame.mouse.1 - origin.1).abs() > self.policy.move_threshold;
+        let policy = &self.policy;
+        let casual = !policy.kiosk && (
+            (policy.on_key && !frame.pressed.is_empty())
+            || (policy.on_click && frame.clicked)
+            || (policy.on_touch && frame.touched)
+            || (policy.on_mouse_move && moved)
+        );
+        let chord = self.chord.as_ref().is_some_and(|c| c.pressed(frame));
+        if !casual && !chord {
+            return false;
+        }
+
+        if policy.pin.is_empty() {
+            return true;
+        }
+        self.pin_entry = Some(PinEntry { typed: String::new(), last_input: frame.time, wrong: false });
+        false
+    }
+
+    fn update_pin(&mut self, frame: &InputFrame) -> bool {
+        let Some(entry) = self.pin_entry.as_mut() else { return false };
+        if frame.pressed.contains(&KeyCode::Escape) || frame.time - entry.last_input > PIN_TIMEOUT_SECS {
+            self.pin_entry = None;
+            self.mouse_origin = None;
+            return false;
+        }
+
+        if !frame.pressed.is_empty() || !frame.chars.is_empty() {
+            entry.last_input = frame.time;
+        }
+        if frame.pressed.contains(&KeyCode::Backspace) {
+            entry.typed.pop();
+        }
+        entry.typed.extend(frame.chars.iter().filter(|c| !c.is_control()));
+        if frame.pressed.contains(&KeyCode::Enter) || frame.pressed.contains(&KeyCode::KpEnter) {
+            if entry.typed == self.policy.pin {
+                return true;
+            }
+            entry.typed.clear();
+            entry.wrong = true;
+        }
+        false
+    }
+
+    /// While the PIN prompt is open: how many characters are typed, and whether the last try was wrong.
+    pub fn pin_prompt(&self) -> Option<(usize, bool)> {
+        self.pin_entry.as_ref().map(|e| (e.typed.chars().count(), e.wrong))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(time: f64) -> InputFrame {
+        InputFrame { time, ..InputFrame::default() }
+    }
+
+    fn keys(time: f64, pressed: &[KeyCode], down: &[KeyCode]) -> InputFrame {
+        InputFrame {
+            pressed: pressed.iter().copied().collect(),
+            down: down.iter().copied().collect(),
+            ..frame(time)
+        }
+    }
+
+    #[test]
+    fn test_parse_chord() {
+        let chord: KeyChord = "Ctrl+Shift+Q".parse().unwrap();
+        assert_eq!(chord.0[2], vec![KeyCode::Q]);
+        assert_eq!("f11".parse::<KeyChord>(), Ok(KeyChord(vec![vec![KeyCode::F11]])));
+        assert_eq!("Alt + 7".parse::<KeyChord>().unwrap().0[1], vec![KeyCode::Key7]);
+        assert!("Ctrl+Banana".parse::<KeyChord>().is_err());
+        assert!("F13".parse::<KeyChord>().is_err());
+        assert!("".parse::<KeyChord>().is_err());
+
+        // Held modifiers plus the key pressed this frame
+        assert!(chord.pressed(&keys(0.0, &[KeyCode::Q], &[KeyCode::RightControl, KeyCode::LeftShift])));
+        assert!(!chord.pressed(&keys(0.0, &[KeyCode::Q], &[KeyCode::LeftControl])));
+        assert!(!chord.pressed(&keys(0.0, &[], &[KeyCode::LeftControl, KeyCode::LeftShift, KeyCode::Q])));
+        assert!(!chord.pressed(&keys(0.0, &[KeyCode::Q], &[KeyCode::LeftControl, KeyCode::LeftShift, KeyCode::LeftAlt])));
+    }
+
+    #[test]
+    fn test_hotkeys() {
+        let bindings = HashMap::from([
+            (HotkeyAction::ToggleSeconds, "S".to_string()),
+            (HotkeyAction::CycleTheme, "Ctrl+S".to_string()),
+            (HotkeyAction::ScaleUp, "Hyper+9".to_string()),
+            (HotkeyAction::ScaleDown, String::new()),
+        ]);
+        let hotkeys = Hotkeys::new(&bindings);
+        assert_eq!(hotkeys.0.len(), 2);
+        assert_eq!(hotkeys.pressed(&keys(0.0, &[KeyCode::S], &[])), vec![HotkeyAction::ToggleSeconds]);
+        assert_eq!(hotkeys.pressed(&keys(0.0, &[KeyCode::S], &[KeyCode::RightControl])), vec![HotkeyAction::CycleTheme]);
+        assert!(hotkeys.pressed(&keys(0.0, &[], &[KeyCode::S])).is_empty());
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let mut watcher = ExitWatcher::new(&ExitPolicy::default(), 1.0);
+        // Keys and movement during the grace period are ignored
+        assert!(!watcher.update(&keys(1.2, &[KeyCode::Space], &[])));
+        assert!(!watcher.update(&InputFrame { mouse: (300.0, 300.0), ..frame(1.4) }));
+
+        assert!(!watcher.update(&InputFrame { mouse: (300.0, 300.0), ..frame(1.6) }));
+        assert!(!watcher.update(&InputFrame { mouse: (308.0, 291.0), ..frame(1.7) }));
+        assert!(watcher.update(&InputFrame { mouse: (311.0, 300.0), ..frame(1.8) }));
+
+        let mut watcher = ExitWatcher::new(&ExitPolicy::default(), 0.0);
+        assert!(watcher.update(&InputFrame { clicked: true, ..frame(1.0) }));
+    }
+
+    #[test]
+    fn test_selected_inputs_only() {
+        let policy = ExitPolicy { on_mouse_move: false, on_click: false, ..ExitPolicy::default() };
+        let mut watcher = ExitWatcher::new(&policy, 0.0);
+        assert!(!watcher.update(&InputFrame { mouse: (0.0, 0.0), ..frame(1.0) }));
+        assert!(!watcher.update(&InputFrame { mouse: (500.0, 0.0), clicked: true, ..frame(1.1) }));
+        assert!(watcher.update(&InputFrame { touched: true, ..frame(1.2) }));
+    }
+
+    #[test]
+    fn test_kiosk_needs_chord() {
+        let policy = ExitPolicy { kiosk: true, chord: "Ctrl+Q".to_string(), ..ExitPolicy::default() };
+        let mut watcher = ExitWatcher::new(&policy, 0.0);
+        assert!(!watcher.update(&keys(1.0, &[KeyCode::Q], &[])));
+        assert!(!watcher.update(&InputFrame { mouse: (900.0, 900.0), clicked: true, touched: true, ..frame(1.1) }));
+        assert!(watcher.update(&keys(1.2, &[KeyCode::Q], &[KeyCode::LeftControl])));
+    }
+
+    #[test]
+    fn test_pin_prompt() {
+        let policy = ExitPolicy { pin: "2468".to_string(), ..ExitPolicy::default() };
+        let mut watcher = ExitWatcher::new(&policy, 0.0);
+        assert!(!watcher.update(&keys(1.0, &[KeyCode::Key1], &[])));
+        assert_eq!(watcher.pin_prompt(), Some((0, false)));
+
+        let typing = |time, text: &str| InputFrame { chars: text.chars().collect(), ..frame(time) };
+        assert!(!watcher.update(&typing(1.1, "1357")));
+        assert!(!watcher.update(&keys(1.2, &[KeyCode::Enter], &[])));
+        assert_eq!(watcher.pin_prompt(), Some((0, true)));
+
+        assert!(!watcher.update(&typing(1.3, "24689")));
+        assert!(!watcher.update(&keys(1.4, &[KeyCode::Backspace], &[])));
+        assert_eq!(watcher.pin_prompt(), Some((4, true)));
+        assert!(watcher.update(&keys(1.5, &[KeyCode::Enter], &[])));
+
+        // Left alone, the prompt closes again
+        let mut watcher = ExitWatcher::new(&policy, 0.0);
+        watcher.update(&InputFrame { clicked: true, ..frame(1.0) });
+        assert!(watcher.pin_prompt().is_some());
+        assert!(!watcher.update(&frame(12.0)));
+        assert!(watcher.pin_prompt().is_none());
+    }
+}
@@ -0,0 +1,481 @@
+//! Installing the clock as the system screensaver.
+//!
+//! Each platform builds a plan of file changes before touching anything, so setup
+//! can show a dry run, and uninstalling is a plan of its own. Windows copies the
+//! executable into System32 as a `.scr`; Linux adds an XScreenSaver program entry,
+//! a launcher `.desktop` file and, optionally, a systemd user unit that starts
+//! XScreenSaver at login, enabled the way `systemctl --user enable` does it.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InstallOptions {
+    /// Linux only: start XScreenSaver at login through a systemd user unit.
+    pub systemd_unit: bool,
+}
+
+/// One step of an install or uninstall plan.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// Create or replace a file.
+    #[cfg_attr(windows, allow(dead_code))]
+    Write { path: PathBuf, contents: String },
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Copy { from: PathBuf, to: PathBuf },
+    /// Create or replace a symlink at `path` pointing to `target`.
+    #[cfg_attr(windows, allow(dead_code))]
+    Link { target: PathBuf, path: PathBuf },
+    /// Delete a file; missing files are skipped.
+    Remove { path: PathBuf },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Write { path, .. } => write!(f, "Write {}", path.display()),
+            Change::Copy { from, to } => write!(f, "Copy {} to {}", from.display(), to.display()),
+            Change::Link { target, path } => write!(f, "Link {} to {}", path.display(), target.display()),
+            Change::Remove { path } => write!(f, "Remove {}", path.display()),
+        }
+    }
+}
+
+/// What is installed right now, for the setup bottom bar.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    pub installed: bool,
+    pub summary: String,
+}
+
+pub fn install_plan(options: &InstallOptions) -> Result<Vec<Change>, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Could not locate current executable: {}", e))?;
+    backend::install_plan(exe, options)
+}
+
+pub fn uninstall_plan() -> Result<Vec<Change>, String> {
+    backend::uninstall_plan()
+}
+
+/// Carries out a plan, stopping at the first change that fails.
+pub fn apply(plan: &[Change]) -> Result<(), String> {
+    backend::check_access()?;
+    apply_changes(plan)
+}
+
+fn apply_changes(plan: &[Change]) -> Result<(), String> {
+    for change in plan {
+        apply_change(change).map_err(|e| format!("{} failed: {}", change, e))?;
+    }
+    Ok(())
+}
+
+fn apply_change(change: &Change) -> io::Result<()> {
+    match change {
+        Change::Write { path, contents } => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)
+        }
+        Change::Copy { from, to } => fs::copy(from, to).map(|_| ()),
+        Change::Link { target, path } => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if fs::symlink_metadata(path).is_ok() {
+                fs::remove_file(path)?;
+            }
+            #[cfg(unix)]
+            return std::os::unix::fs::symlink(target, path);
+            #[cfg(not(unix))]
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("Can't link to {}", target.display())));
+        }
+        Change::Remove { path } => match fs::remove_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+    }
+}
+
+pub fn status() -> Status {
+    backend::status()
+}
+
+/// Whether the current platform has an installer at all.
+pub fn supported() -> bool {
+    cfg!(any(windows, target_os = "linux"))
+}
+
+#[cfg(windows)]
+mod backend {
+    use std::path::PathBuf;
+
+    use super::{Change, InstallOptions, Status};
+
+    fn target() -> PathBuf {
+        let root = std::env::var_os("SystemRoot").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(r"C:\Windows"));
+        root.join("System32").join("rust_flip_clock.scr")
+    }
+
+    pub fn install_plan(exe: PathBuf, _options: &InstallOptions) -> Result<Vec<Change>, String> {
+        Ok(vec![Change::Copy { from: exe, to: target() }])
+    }
+
+    pub fn uninstall_plan() -> Result<Vec<Change>, String> {
+        Ok(vec![Change::Remove { path: target() }])
+    }
+
+    /// Writing to System32 needs an elevated process.
+    pub fn check_access() -> Result<(), String> {
+        if is_elevated() {
+            Ok(())
+        } else {
+            Err("Administrator rights are needed to change System32. Run setup as administrator.".to_string())
+        }
+    }
+
+    fn is_elevated() -> bool {
+        use winapi::um::handleapi::CloseHandle;
+        use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+        use winapi::um::securitybaseapi::GetTokenInformation;
+        use winapi::um::winnt::{TokenElevation, HANDLE, TOKEN_ELEVATION, TOKEN_QUERY};
+
+        unsafe {
+            let mut token: HANDLE = std::ptr::null_mut();
+            if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+                return false;
+            }
+            let mut elevation: TOKEN_ELEVATION = std::mem::zeroed();
+            let mut size = std::mem::size_of::<TOKEN_ELEVATION>() as u32;
+            let ok = GetTokenInformation(token, TokenElevation, &mut elevation as *mut _ as *mut _, size, &mut size);
+            CloseHandle(token);
+            ok != 0 && elevation.TokenIsElevated != 0
+        }
+    }
+
+    pub fn status() -> Status {
+        let target = target();
+        if target.exists() {
+            Status { installed: true, summary: format!("Installed at {}", target.display()) }
+        } else {
+            Status { installed: false, summary: "Not installed".to_string() }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod backend {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use directories::BaseDirs;
+
+    use super::{Change, InstallOptions, Status};
+
+    /// Marks our line in the XScreenSaver `programs:` list.
+    const PROGRAM_LABEL: &str = "\"Flip Clock\"";
+    const DESKTOP_FILE: &str = "rust_flip_clock.desktop";
+    const UNIT_FILE: &str = "rust_flip_clock-xscreensaver.service";
+    /// Matches the unit's `WantedBy=`; a link here starts it with the session.
+    const WANTS_DIR: &str = "graphical-session.target.wants";
+
+    /// Where the per-user files live.
+    pub struct Dirs {
+        /// `~/.xscreensaver`
+        pub xscreensaver: PathBuf,
+        /// `~/.local/share/applications`
+        pub applications: PathBuf,
+        /// `~/.config/systemd/user`
+        pub systemd_user: PathBuf,
+    }
+
+    impl Dirs {
+        fn from_home() -> Result<Self, String> {
+            let base = BaseDirs::new().ok_or("Could not find the home directory")?;
+            Ok(Self {
+                xscreensaver: base.home_dir().join(".xscreensaver"),
+                applications: base.data_dir().join("applications"),
+                systemd_user: base.config_dir().join("systemd").join("user"),
+            })
+        }
+    }
+
+    pub fn install_plan(exe: PathBuf, options: &InstallOptions) -> Result<Vec<Change>, String> {
+        plan_install_in(&Dirs::from_home()?, &exe, options)
+    }
+
+    pub fn uninstall_plan() -> Result<Vec<Change>, String> {
+        Ok(plan_uninstall_in(&Dirs::from_home()?))
+    }
+
+    /// Everything goes under the home directory.
+    pub fn check_access() -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn status() -> Status {
+        match Dirs::from_home() {
+            Ok(dirs) => status_in(&dirs),
+            Err(e) => Status { installed: false, summary: e },
+        }
+    }
+
+    pub fn plan_install_in(dirs: &Dirs, exe: &Path, options: &InstallOptions) -> Result<Vec<Change>, String> {
+        let exe = exe.to_str().ok_or("The executable path is not valid UTF-8")?;
+        let existing = fs::read_to_string(&dirs.xscreensaver).unwrap_or_default();
+        let mut plan = vec![
+            Change::Write {
+                path: dirs.xscreensaver.clone(),
+                contents: add_program(&remove_program(&existing), &format!("{}  {} -root", PROGRAM_LABEL, shell_quote(exe))),
+            },
+            Change::Write { path: dirs.applications.join(DESKTOP_FILE), contents: desktop_entry(exe) },
+        ];
+        let unit = dirs.systemd_user.join(UNIT_FILE);
+        let wanted = wanted_link(dirs);
+        if options.systemd_unit {
+            plan.push(Change::Write { path: unit.clone(), contents: systemd_unit() });
+            plan.push(Change::Link { target: unit, path: wanted });
+        } else {
+            plan.extend(removals([wanted, unit]));
+        }
+        Ok(plan)
+    }
+
+    fn wanted_link(dirs: &Dirs) -> PathBuf {
+        dirs.systemd_user.join(WANTS_DIR).join(UNIT_FILE)
+    }
+
+    /// Removes the paths that exist; a link counts even when what it points to is gone.
+    fn removals(paths: impl IntoIterator<Item = PathBuf>) -> impl Iterator<Item = Change> {
+        paths.into_iter()
+            .filter(|path| fs::symlink_metadata(path).is_ok())
+            .map(|path| Change::Remove { path })
+    }
+
+    pub fn plan_uninstall_in(dirs: &Dirs) -> Vec<Change> {
+        let mut plan = Vec::new();
+        if let Ok(existing) = fs::read_to_string(&dirs.xscreensaver) {
+            let contents = remove_program(&existing);
+            if contents != existing {
+                plan.push(Change::Write { path: dirs.xscreensaver.clone(), contents });
+            }
+        }
+        plan.extend(removals([dirs.applications.join(DESKTOP_FILE), wanted_link(dirs), dirs.systemd_user.join(UNIT_FILE)]));
+        plan
+    }
+
+    pub fn status_in(dirs: &Dirs) -> Status {
+        let in_programs = fs::read_to_string(&dirs.xscreensaver)
+            .map(|c| c.contains(PROGRAM_LABEL))
+            .unwrap_or(false);
+        let mut parts = Vec::new();
+        if in_programs {
+            parts.push("XScreenSaver entry");
+        }
+        if dirs.applications.join(DESKTOP_FILE).exists() {
+            parts.push("launcher");
+        }
+        if dirs.systemd_user.join(UNIT_FILE).exists() {
+            parts.push("systemd unit");
+        }
+        if fs::symlink_metadata(wanted_link(dirs)).is_ok() {
+            parts.push("starts at login");
+        }
+        // Anything left behind counts, so a partial install can still be uninstalled
+        match parts.is_empty() {
+            true => Status { installed: false, summary: "Not installed".to_string() },
+            false => Status { installed: true, summary: format!("Installed: {}", parts.join(", ")) },
+        }
+    }
+
+    /// Adds `entry` at the top of the `programs:` resource, creating it if needed.
+    ///
+    /// Every line of the list but the last ends in `\n\`, the last in `\n`.
+    pub fn add_program(existing: &str, entry: &str) -> String {
+        let mut lines: Vec<String> = existing.lines().map(str::to_string).collect();
+        match lines.iter().position(|l| l.trim_start().starts_with("programs:")) {
+            Some(i) => {
+                let line = lines[i].trim_end().to_string();
+                if let Some(head) = line.strip_suffix('\\') {
+                    lines[i] = format!("{}\\", head);
+                    lines.insert(i + 1, format!("\t\t\t\t{} \\n\\", entry));
+                } else {
+                    // The list was on one line, or empty
+                    let value = line.trim_start()["programs:".len()..].trim().to_string();
+                    lines[i] = "programs:\t\t\t\t\t\t\t\t      \\".to_string();
+                    if value.is_empty() {
+                        lines.insert(i + 1, format!("\t\t\t\t{} \\n", entry));
+                    } else {
+                        lines.insert(i + 1, format!("\t\t\t\t{} \\n\\", entry));
+                        lines.insert(i + 2, format!("\t\t\t\t{}", value));
+                    }
+                }
+            }
+            None => {
+                lines.push("programs:\t\t\t\t\t\t\t\t      \\".to_string());
+                lines.push(format!("\t\t\t\t{} \\n", entry));
+            }
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Drops our line from the `programs:` list, keeping the continuations intact.
+    pub fn remove_program(existing: &str) -> String {
+        let mut lines: Vec<String> = existing.lines().map(str::to_string).collect();
+        while let Some(i) = lines.iter().position(|l| l.contains(PROGRAM_LABEL)) {
+            let removed = lines.remove(i);
+            // We were the last entry, so the line before must stop continuing
+            if !removed.trim_end().ends_with('\\') && i > 0 {
+                let prev = lines[i - 1].trim_end();
+                let prev = prev.strip_suffix('\\').unwrap_or(prev).trim_end().to_string();
+                lines[i - 1] = prev;
+            }
+        }
+        if lines.is_empty() {
+            return String::new();
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// XScreenSaver runs the command through `/bin/sh`.
+    fn shell_quote(text: &str) -> String {
+        if text.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+".contains(c)) {
+            text.to_string()
+        } else {
+            format!("'{}'", text.replace('\'', "'\\''"))
+        }
+    }
+
+    fn desktop_entry(exe: &str) -> String {
+        format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=Flip Clock\n\
+             Comment=Flip clock screensaver settings\n\
+             Exec=\"{}\" --setup\n\
+             Categories=Utility;Clock;\n\
+             Terminal=false\n",
+            exe.replace('\\', "\\\\").replace('"', "\\\"")
+        )
+    }
+
+    fn systemd_unit() -> String {
+        "[Unit]\n\
+         Description=XScreenSaver (Flip Clock)\n\
+         PartOf=graphical-session.target\n\
+         After=graphical-session.target\n\
+         \n\
+         [Service]\n\
+         ExecStart=xscreensaver -no-splash\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=graphical-session.target\n"
+            .to_string()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn dirs(root: &Path) -> Dirs {
+            Dirs {
+                xscreensaver: root.join(".xscreensaver"),
+                applications: root.join("applications"),
+                systemd_user: root.join("systemd"),
+            }
+        }
+
+        const PROGRAMS: &str = "mode:\t\trandom\n\
+            programs:\t\t\t\t\t\t\t\t      \\\n\
+            \t\t\t\tmaze -root\t\t\t\t    \\n\\\n\
+            \t\t\t\txlyap -root\t\t\t\t    \\n\n\
+            pointerHysteresis: 10\n";
+
+        #[test]
+        fn test_program_entry_round_trip() {
+            let added = add_program(PROGRAMS, "\"Flip Clock\"  /opt/clock -root");
+            let lines: Vec<&str> = added.lines().collect();
+            assert_eq!(lines[2], "\t\t\t\t\"Flip Clock\"  /opt/clock -root \\n\\");
+            assert_eq!(lines[3].trim(), "maze -root\t\t\t\t    \\n\\");
+            assert_eq!(remove_program(&added), PROGRAMS);
+
+            // Replacing an old entry leaves just one
+            let again = add_program(&remove_program(&added), "\"Flip Clock\"  /usr/bin/clock -root");
+            assert_eq!(again.matches(PROGRAM_LABEL).count(), 1);
+        }
+
+        #[test]
+        fn test_program_entry_in_empty_list() {
+            let added = add_program("programs:\nlock: False\n", "\"Flip Clock\"  /opt/clock -root");
+            assert_eq!(added.lines().nth(1), Some("\t\t\t\t\"Flip Clock\"  /opt/clock -root \\n"));
+            assert_eq!(remove_program(&added), "programs:\nlock: False\n");
+
+            let created = add_program("", "\"Flip Clock\"  /opt/clock -root");
+            assert!(created.starts_with("programs:"));
+            assert_eq!(remove_program(&created), "programs:\n");
+        }
+
+        #[test]
+        fn test_install_and_uninstall_plans() {
+            let root = tempfile::tempdir().unwrap();
+            let dirs = dirs(root.path());
+            let exe = Path::new("/opt/flip clock/rust_flip-rs");
+
+            let plan = plan_install_in(&dirs, exe, &InstallOptions::default()).unwrap();
+            assert_eq!(plan.len(), 2);
+            assert!(matches!(&plan[1], Change::Write { contents, .. } if contents.contains("Exec=\"/opt/flip clock/rust_flip-rs\" --setup")));
+            assert!(!status_in(&dirs).installed);
+
+            let plan = plan_install_in(&dirs, exe, &InstallOptions { systemd_unit: true }).unwrap();
+            assert_eq!(plan.len(), 4);
+            super::super::apply_changes(&plan).unwrap();
+            assert_eq!(status_in(&dirs).summary, "Installed: XScreenSaver entry, launcher, systemd unit, starts at login");
+            let wanted = dirs.systemd_user.join(WANTS_DIR).join(UNIT_FILE);
+            assert_eq!(fs::read_link(&wanted).unwrap(), dirs.systemd_user.join(UNIT_FILE));
+
+            // Turning the option off takes the unit and its link away again
+            let plan = plan_install_in(&dirs, exe, &InstallOptions::default()).unwrap();
+            assert_eq!(plan[2..], [Change::Remove { path: wanted.clone() }, Change::Remove { path: dirs.systemd_user.join(UNIT_FILE) }]);
+
+            let plan = plan_uninstall_in(&dirs);
+            assert_eq!(plan[0], Change::Write { path: dirs.xscreensaver.clone(), contents: "programs:\n".to_string() });
+            super::super::apply_changes(&plan).unwrap();
+            assert_eq!(status_in(&dirs).summary, "Not installed");
+            assert!(plan_uninstall_in(&dirs).is_empty());
+
+            // Leftovers from a partial uninstall still show as installed, so they can be removed
+            fs::create_dir_all(&dirs.applications).unwrap();
+            fs::write(dirs.applications.join(DESKTOP_FILE), "").unwrap();
+            assert!(status_in(&dirs).installed);
+            assert_eq!(plan_uninstall_in(&dirs).len(), 1);
+            assert_eq!(shell_quote("/opt/flip clock/it's"), "'/opt/flip clock/it'\\''s'");
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+mod backend {
+    use std::path::PathBuf;
+
+    use super::{Change, InstallOptions, Status};
+
+    pub fn install_plan(_exe: PathBuf, _options: &InstallOptions) -> Result<Vec<Change>, String> {
+        Err("Installing is not supported on this platform".to_string())
+    }
+
+    pub fn uninstall_plan() -> Result<Vec<Change>, String> {
+        Err("Installing is not supported on this platform".to_string())
+    }
+
+    pub fn check_access() -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn status() -> Status {
+        Status { installed: false, summary: "Not supported on this platform".to_string() }
+    }
+}
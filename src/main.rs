@@ -1,25 +1,59 @@
 use macroquad::prelude::*;
-use chrono::{Local, Timelike, Utc, TimeZone, FixedOffset};
+use chrono::{DateTime, Timelike, Utc};
+use std::collections::HashMap;
 use std::env;
-use std::path::Path;
+use std::rc::Rc;
 use egui_macroquad::egui;
 use macroquad::miniquad;
 
+mod cli;
 mod config;
-use config::{load_config, save_config, AppConfig, ViewType};
+mod control;
+mod departure_board;
+mod input;
+mod installer;
+mod message_board;
+mod migrations;
+mod monitor_identity;
+mod profiles;
+mod split_flap;
+mod time_source;
+#[cfg(target_os = "linux")]
+mod x11_utils;
+use cli::{Cli, Command};
+use config::{
+    load_config, load_config_or_reset, save_config, AppConfig, ConfigSaver, ConfigWatcher, SystemConfig, BoardCity, BoardColumn, BoardMessage, FlapStagger, HotkeyAction, MonitorSettings, TextAlign, ViewType,
+    WindowGeometry,
+};
+use control::{ControlCommand, ControlServer};
+use departure_board::DepartureBoardState;
+use input::{ExitWatcher, Hotkeys, InputFrame};
+use message_board::MessageBoardState;
+use monitor_identity::identify_monitors;
+use profiles::{Day, ProfileEdit, ProfileRule};
+use time_source::TimeSource;
 
 #[cfg(windows)]
 mod windows_utils {
     use winapi::um::winuser::{EnumDisplayMonitors, GetMonitorInfoW, MONITORINFOEXW, MONITORINFOF_PRIMARY,
         GetSystemMetrics, SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN,
         SetWindowPos, SetWindowLongW, GetWindowLongW, HWND_TOP, SWP_SHOWWINDOW,
-        GWL_STYLE, WS_POPUP, WS_VISIBLE, GetForegroundWindow
+        GWL_STYLE, WS_POPUP, WS_VISIBLE, GetForegroundWindow,
+        GetActiveWindow, GetClientRect, IsWindow, SetParent, WS_CHILD,
+        GetWindowRect, HWND_TOPMOST, HWND_NOTOPMOST, SWP_NOMOVE, SWP_NOSIZE, SWP_FRAMECHANGED, WS_OVERLAPPEDWINDOW
     };
-    use winapi::shared::windef::{HMONITOR, HDC, LPRECT, HWND};
+    use winapi::um::winuser::{EnumDisplayDevicesW, EDD_GET_DEVICE_INTERFACE_NAME};
+    use winapi::um::wingdi::DISPLAY_DEVICEW;
+    use winapi::um::winreg::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_BINARY};
+    use winapi::shared::windef::{HMONITOR, HDC, LPRECT, HWND, RECT};
     use winapi::shared::minwindef::{BOOL, LPARAM, TRUE};
+    use winapi::shared::winerror::ERROR_SUCCESS;
     use std::ffi::OsString;
     use std::os::windows::ffi::OsStringExt;
+    use std::sync::atomic::{AtomicIsize, Ordering};
     use macroquad::prelude::Rect;
+    use crate::config::WindowGeometry;
+    use crate::monitor_identity::{parse_edid, Edid};
 
     #[derive(Clone, Debug)]
     pub struct MonitorInfo {
@@ -29,6 +63,7 @@ mod windows_utils {
         pub width: i32,
         pub height: i32,
         pub is_primary: bool,
+        pub edid: Option<Edid>,
     }
 
     unsafe extern "system" fn monitor_enum_proc(hmonitor: HMONITOR, _: HDC, _: LPRECT, lparam: LPARAM) -> BOOL {
@@ -54,11 +89,48 @@ mod windows_utils {
                 width,
                 height,
                 is_primary: (info.dwFlags & MONITORINFOF_PRIMARY) != 0,
+                edid: read_edid(&info.szDevice[0..len]).and_then(|data| parse_edid(&data)),
             });
         }
         TRUE
     }
 
+    /// The EDID of the monitor on display `device` (e.g. `\\.\DISPLAY2`), as Windows keeps it in the registry.
+    unsafe fn read_edid(device: &[u16]) -> Option<Vec<u8>> {
+        let device: Vec<u16> = device.iter().copied().chain(Some(0)).collect();
+        let mut display: DISPLAY_DEVICEW = std::mem::zeroed();
+        display.cb = std::mem::size_of::<DISPLAY_DEVICEW>() as u32;
+        if EnumDisplayDevicesW(device.as_ptr(), 0, &mut display, EDD_GET_DEVICE_INTERFACE_NAME) == 0 {
+            return None;
+        }
+
+        // e.g. \\?\DISPLAY#DEL40F5#5&2b2d0f3b&0&UID4352#{e6f07b5f-ee97-4a90-b076-33f57bf4eaa7}
+        let len = display.DeviceID.iter().position(|&c| c == 0).unwrap_or(display.DeviceID.len());
+        let interface = String::from_utf16_lossy(&display.DeviceID[..len]);
+        let mut parts = interface.split('#').skip(1);
+        let (hardware_id, instance) = (parts.next()?, parts.next()?);
+        let key = format!("SYSTEM\\CurrentControlSet\\Enum\\DISPLAY\\{}\\{}\\Device Parameters", hardware_id, instance);
+        let key: Vec<u16> = key.encode_utf16().chain(Some(0)).collect();
+        let value: Vec<u16> = "EDID".encode_utf16().chain(Some(0)).collect();
+
+        let mut data = vec![0u8; 4096];
+        let mut size = data.len() as u32;
+        let status = RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_BINARY,
+            std::ptr::null_mut(),
+            data.as_mut_ptr() as *mut _,
+            &mut size,
+        );
+        if status != ERROR_SUCCESS as i32 {
+            return None;
+        }
+        data.truncate(size as usize);
+        Some(data)
+    }
+
     pub fn get_monitors() -> Vec<MonitorInfo> {
         let mut monitors = Vec::new();
         unsafe {
@@ -122,11 +194,84 @@ mod windows_utils {
             );
         }
     }
+
+    /// Re-parents our window into the Screen Saver Settings preview pane.
+    pub fn embed_in_preview(parent: isize) {
+        unsafe {
+            let parent = parent as HWND;
+            let hwnd: HWND = GetActiveWindow();
+            if hwnd.is_null() || IsWindow(parent) == 0 { return; }
+
+            let mut rect: RECT = std::mem::zeroed();
+            GetClientRect(parent, &mut rect);
+            SetParent(hwnd, parent);
+            SetWindowLongW(hwnd, GWL_STYLE, (WS_CHILD | WS_VISIBLE) as i32);
+            SetWindowPos(
+                hwnd,
+                HWND_TOP,
+                0, 0,
+                rect.right - rect.left,
+                rect.bottom - rect.top,
+                SWP_SHOWWINDOW
+            );
+        }
+    }
+
+    /// The desk clock window, kept from when it was set up since it may not stay in the foreground.
+    static DESK_WINDOW: AtomicIsize = AtomicIsize::new(0);
+
+    pub fn set_desk_window(geometry: Option<WindowGeometry>, borderless: bool, always_on_top: bool) {
+        unsafe {
+            let mut hwnd = DESK_WINDOW.load(Ordering::SeqCst) as HWND;
+            if hwnd.is_null() {
+                hwnd = GetForegroundWindow();
+                if hwnd.is_null() { return; }
+                DESK_WINDOW.store(hwnd as isize, Ordering::SeqCst);
+            }
+
+            let frame = if borderless { WS_POPUP } else { WS_OVERLAPPEDWINDOW };
+            SetWindowLongW(hwnd, GWL_STYLE, (frame | WS_VISIBLE) as i32);
+
+            let insert_after = if always_on_top { HWND_TOPMOST } else { HWND_NOTOPMOST };
+            match geometry {
+                Some(g) => SetWindowPos(hwnd, insert_after, g.x, g.y, g.width as i32, g.height as i32,
+                    SWP_SHOWWINDOW | SWP_FRAMECHANGED),
+                None => SetWindowPos(hwnd, insert_after, 0, 0, 0, 0,
+                    SWP_NOMOVE | SWP_NOSIZE | SWP_SHOWWINDOW | SWP_FRAMECHANGED),
+            };
+        }
+    }
+
+    pub fn window_geometry() -> Option<WindowGeometry> {
+        unsafe {
+            let hwnd = DESK_WINDOW.load(Ordering::SeqCst) as HWND;
+            let mut rect: RECT = std::mem::zeroed();
+            if hwnd.is_null() || GetWindowRect(hwnd, &mut rect) == 0 { return None; }
+            Some(WindowGeometry {
+                x: rect.left,
+                y: rect.top,
+                width: (rect.right - rect.left).max(0) as u32,
+                height: (rect.bottom - rect.top).max(0) as u32,
+            })
+        }
+    }
+
+    /// xscreensaver hacks are X11-only.
+    pub fn embed_in_hack_window(_window: Option<u32>) -> Option<Rect> {
+        None
+    }
+
+    /// The preview pane goes away when the settings dialog closes or picks another screensaver.
+    pub fn window_exists(hwnd: isize) -> bool {
+        unsafe { IsWindow(hwnd as HWND) != 0 }
+    }
 }
 
 #[cfg(not(windows))]
 mod windows_utils {
     use macroquad::prelude::Rect;
+    use crate::config::WindowGeometry;
+    use crate::monitor_identity::Edid;
 
     #[derive(Clone, Debug)]
     pub struct MonitorInfo {
@@ -136,54 +281,121 @@ mod windows_utils {
         pub width: i32,
         pub height: i32,
         pub is_primary: bool,
+        pub edid: Option<Edid>,
     }
     pub fn get_monitors() -> Vec<MonitorInfo> {
+        #[cfg(target_os = "linux")]
+        if let Some(monitors) = crate::x11_utils::get_monitors() {
+            return monitors;
+        }
         vec![MonitorInfo {
             name: "Default".to_string(),
             x: 0, y: 0, width: 1920, height: 1080,
-            is_primary: true
+            is_primary: true,
+            edid: None,
         }]
     }
     pub fn get_virtual_screen_rect() -> Rect {
-        Rect::new(0.0, 0.0, 1920.0, 1080.0)
+        bounding_rect(&get_monitors())
+    }
+
+    /// The smallest rect containing every monitor.
+    pub fn bounding_rect(monitors: &[MonitorInfo]) -> Rect {
+        let left = monitors.iter().map(|m| m.x).min().unwrap_or(0);
+        let top = monitors.iter().map(|m| m.y).min().unwrap_or(0);
+        let right = monitors.iter().map(|m| m.x + m.width).max().unwrap_or(0);
+        let bottom = monitors.iter().map(|m| m.y + m.height).max().unwrap_or(0);
+        Rect::new(left as f32, top as f32, (right - left) as f32, (bottom - top) as f32)
+    }
+    pub fn make_window_cover_virtual_screen() {
+        #[cfg(target_os = "linux")]
+        crate::x11_utils::cover_virtual_screen();
+    }
+    pub fn restore_window() {
+        #[cfg(target_os = "linux")]
+        crate::x11_utils::restore_window();
+    }
+    pub fn embed_in_preview(_parent: isize) {}
+
+    pub fn set_desk_window(_geometry: Option<WindowGeometry>, _borderless: bool, _always_on_top: bool) {
+        #[cfg(target_os = "linux")]
+        crate::x11_utils::set_desk_window(_geometry, _borderless, _always_on_top);
+    }
+    pub fn window_geometry() -> Option<WindowGeometry> {
+        #[cfg(target_os = "linux")]
+        return crate::x11_utils::window_geometry();
+        #[cfg(not(target_os = "linux"))]
+        None
     }
-    pub fn make_window_cover_virtual_screen() {}
-    pub fn restore_window() {}
+
+    /// Moves our window into an xscreensaver window (over the root window if `None`)
+    /// and returns the area it fills on the desktop.
+    pub fn embed_in_hack_window(_window: Option<u32>) -> Option<Rect> {
+        #[cfg(target_os = "linux")]
+        return crate::x11_utils::embed_in(_window);
+        #[cfg(not(target_os = "linux"))]
+        None
+    }
+    pub fn window_exists(_hwnd: isize) -> bool { true }
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
 struct ClockState {
     current_digits: [String; 4], // HH MM
     current_seconds: [String; 2],
     previous_digits: [String; 4],
     previous_seconds: [String; 2],
     animation_start: Option<f64>,
+    countdown_end: Option<DateTime<Utc>>, // Shows time left instead of the time of day
+    time: Rc<dyn TimeSource>,
 }
 
 impl ClockState {
-    fn new() -> Self {
+    fn new(time: Rc<dyn TimeSource>) -> Self {
         let mut s = Self {
             current_digits: Default::default(),
             current_seconds: Default::default(),
             previous_digits: Default::default(),
             previous_seconds: Default::default(),
             animation_start: None,
+            countdown_end: None,
+            time,
         };
         s.update(false); // Init
         s.previous_digits = s.current_digits.clone();
         s.previous_seconds = s.current_seconds.clone();
+        s.animation_start = None;
         s
     }
 
+    fn start_countdown(&mut self, duration: std::time::Duration) -> Result<(), String> {
+        let end = chrono::Duration::from_std(duration).ok().and_then(|d| self.time.now().checked_add_signed(d));
+        self.countdown_end = Some(end.ok_or("The timer would end past the end of time")?);
+        Ok(())
+    }
+
+    fn stop_countdown(&mut self) {
+        self.countdown_end = None;
+    }
+
     fn update(&mut self, use_12h: bool) {
-        let now = Local::now();
-        let mut hour = now.hour();
-        if use_12h {
-            hour = hour % 12;
-            if hour == 0 { hour = 12; }
-        }
-        let minute = now.minute();
-        let second = now.second();
+        let (hour, minute, second) = match self.countdown_end {
+            Some(end) => {
+                // Round up so the last second shows 00:00:01, not 00:00:00
+                let left_ms = (end - self.time.now()).num_milliseconds().max(0);
+                let left = ((left_ms + 999) / 1000) as u32;
+                ((left / 3600).min(99), left / 60 % 60, left % 60)
+            }
+            None => {
+                let now = self.time.now_local();
+                let mut hour = now.hour();
+                if use_12h {
+                    hour %= 12;
+                    if hour == 0 { hour = 12; }
+                }
+                (hour, now.minute(), now.second())
+            }
+        };
 
         let new_digits = [
             (hour / 10).to_string(),
@@ -196,165 +408,284 @@ impl ClockState {
             (second % 10).to_string(),
         ];
 
-        if new_digits != self.current_digits || new_seconds != self.current_seconds {
-             if self.animation_start.is_none() {
-                 self.previous_digits = self.current_digits.clone();
-                 self.previous_seconds = self.current_seconds.clone();
-                 self.current_digits = new_digits;
-                 self.current_seconds = new_seconds;
-                 self.animation_start = Some(get_time());
-             }
+        if (new_digits != self.current_digits || new_seconds != self.current_seconds) && self.animation_start.is_none() {
+            self.previous_digits = self.current_digits.clone();
+            self.previous_seconds = self.current_seconds.clone();
+            self.current_digits = new_digits;
+            self.current_seconds = new_seconds;
+            self.animation_start = Some(self.time.seconds());
         }
     }
-}
 
-// --- Departure Board Logic ---
-
-struct CityData {
-    name: &'static str,
-    offset_hours: i32,
-    offset_minutes: i32,
+    /// Flip progress (0.0 - 1.0) of the running animation; settles the digits once it completes.
+    fn progress(&mut self, duration_ms: u64) -> f32 {
+        let Some(start) = self.animation_start else { return 0.0 };
+        let elapsed = (self.time.seconds() - start) * 1000.0;
+        let progress = (elapsed / duration_ms as f64) as f32;
+        if progress >= 1.0 {
+            self.animation_start = None;
+            self.previous_digits = self.current_digits.clone();
+            self.previous_seconds = self.current_seconds.clone();
+            return 1.0;
+        }
+        progress
+    }
 }
 
-const CITIES: &[CityData] = &[
-    CityData { name: "HAWAII", offset_hours: -10, offset_minutes: 0 },
-    CityData { name: "LOS ANGELES", offset_hours: -8, offset_minutes: 0 },
-    CityData { name: "NEW YORK (EST)", offset_hours: -5, offset_minutes: 0 },
-    CityData { name: "UTC", offset_hours: 0, offset_minutes: 0 },
-    CityData { name: "LONDON", offset_hours: 0, offset_minutes: 0 },
-    CityData { name: "STOCKHOLM", offset_hours: 1, offset_minutes: 0 },
-    CityData { name: "PARIS", offset_hours: 1, offset_minutes: 0 },
-    CityData { name: "HANOI", offset_hours: 7, offset_minutes: 0 },
-    CityData { name: "BRISBANE", offset_hours: 10, offset_minutes: 0 },
-    CityData { name: "WELLINGTON", offset_hours: 12, offset_minutes: 0 },
-];
-
-#[derive(Clone)]
-struct DepartureBoardState {
-    // Current display strings per row
-    rows: Vec<RowState>,
-    last_update: f64,
+/// Animation state for every kind of view, so any of them can be drawn into a rect.
+struct ViewStates {
+    clock: ClockState,
+    departure: DepartureBoardState,
+    message: MessageBoardState,
 }
 
-#[derive(Clone)]
-struct RowState {
-    time_str: String,     // HH:MM
-    prev_time_str: String,
-
-    ampm: String,
-    prev_ampm: String,
-
-    day: String,
-    prev_day: String,
+impl ViewStates {
+    fn new(time: Rc<dyn TimeSource>, config: &AppConfig) -> Self {
+        Self {
+            clock: ClockState::new(time.clone()),
+            departure: DepartureBoardState::new(time.clone(), config),
+            message: MessageBoardState::new(time, config),
+        }
+    }
 
-    anim_start: Option<f64>,
-}
+    fn update(&mut self, config: &AppConfig) {
+        self.clock.update(config.use_12h_format);
+        self.departure.sync_cities(&config.board_cities);
+        self.departure.update(config);
+        self.message.update(config);
+    }
 
-impl DepartureBoardState {
-    fn new() -> Self {
-        let mut rows = Vec::new();
-        for _ in CITIES {
-            rows.push(RowState {
-                time_str: "  :  ".to_string(),
-                prev_time_str: "  :  ".to_string(),
-                ampm: "  ".to_string(),
-                prev_ampm: "  ".to_string(),
-                day: "   ".to_string(),
-                prev_day: "   ".to_string(),
-                anim_start: None,
-            });
-        }
-        let mut s = Self { rows, last_update: 0.0 };
-        s.update(); // Initial populate
-        // Set prev = curr to avoid initial flip
-        for row in &mut s.rows {
-            row.prev_time_str = row.time_str.clone();
-            row.prev_ampm = row.ampm.clone();
-            row.prev_day = row.day.clone();
+    /// Applies a control command that affects what the views show.
+    fn apply(&mut self, command: &ControlCommand) -> Result<(), String> {
+        match command {
+            ControlCommand::PushMessage(text) => self.message.push(BoardMessage::new(text)),
+            ControlCommand::StartTimer(duration) => self.clock.start_countdown(*duration)?,
+            ControlCommand::StopTimer => self.clock.stop_countdown(),
+            ControlCommand::SetView { .. } | ControlCommand::Reload => {}
         }
-        s
+        Ok(())
     }
 
-    fn update(&mut self) {
-        let now_utc = Utc::now();
-
-        // Check if we need to update (every second is fine)
-        if get_time() - self.last_update < 0.1 { return; }
-        self.last_update = get_time();
+    fn draw(&mut self, view: &ViewType, config: &AppConfig, rect: Rect, font: Option<&Font>, is_preview: bool) {
+        match view {
+            ViewType::Clock => draw_clock_face(config, &mut self.clock, rect, font, is_preview),
+            ViewType::DepartureBoard => draw_departure_board(config, &mut self.departure, rect, font),
+            ViewType::MessageBoard => draw_message_board(config, &mut self.message, rect, font),
+            ViewType::Off => draw_rectangle(rect.x, rect.y, rect.w, rect.h, BLACK),
+        }
+    }
+}
 
-        for (i, city) in CITIES.iter().enumerate() {
-            // Calculate time for city
-            // Since FixedOffset handles seconds, we do (hours * 3600)
-            let offset_secs = city.offset_hours * 3600 + city.offset_minutes * 60;
-            let tz = FixedOffset::east_opt(offset_secs).unwrap_or(FixedOffset::east_opt(0).unwrap());
-            let city_time = now_utc.with_timezone(&tz);
+#[derive(PartialEq)]
+enum AppMode {
+    /// Full-screen; `preview` goes back to setup on exit.
+    Clock { preview: bool },
+    /// Inside the Windows screensaver settings preview pane.
+    Embedded { parent: isize },
+    /// A normal window; only Escape closes it.
+    Windowed,
+    /// A desk clock left open all day. Input switches views instead of exiting.
+    Desk,
+    /// An xscreensaver hack in `window` (the root window if `None`). The host saver
+    /// watches for input and kills us, so nothing here exits.
+    Hack { window: Option<u32> },
+    Setup,
+}
 
-            let (is_pm, hour_12) = city_time.hour12();
-            let ampm_str = if is_pm { "PM" } else { "AM" };
-            let time_str = format!("{:>2}:{:02}", hour_12, city_time.minute());
+/// `--view` / `--monitor` choices for this run, and views set with `ctl view` since it
+/// started. They are never saved.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ViewOverrides {
+    view: Option<ViewType>,
+    monitor: Option<String>,
+    /// Per-monitor views from `ctl view`, which win over the flags.
+    monitor_views: HashMap<String, ViewType>,
+}
 
-            // For Day: Show day of week (MON, TUE...)
-            // Just always show it as per image
-            let day_str = city_time.format("%a").to_string().to_uppercase();
+impl ViewOverrides {
+    fn apply(&self, config: &mut AppConfig, monitors: &[windows_utils::MonitorInfo]) {
+        self.apply_flags(config, monitors);
+        for (name, view) in &self.monitor_views {
+            config.monitor_views.insert(name.clone(), view.clone());
+        }
+    }
 
-            let row = &mut self.rows[i];
+    fn apply_flags(&self, config: &mut AppConfig, monitors: &[windows_utils::MonitorInfo]) {
+        if let Some(name) = &self.monitor {
+            if !monitors.iter().any(|m| m.name == *name) {
+                let names: Vec<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+                eprintln!("Warning: Unknown monitor '{}' (available: {})", name, names.join(", "));
+                return;
+            }
+        }
 
-            if row.time_str != time_str || row.ampm != ampm_str || row.day != day_str {
-                // If animation already running, finish it instantly
-                if row.anim_start.is_some() {
-                    row.prev_time_str = row.time_str.clone();
-                    row.prev_ampm = row.ampm.clone();
-                    row.prev_day = row.day.clone();
+        match (&self.view, &self.monitor) {
+            (Some(view), Some(name)) => {
+                config.monitor_views.insert(name.clone(), view.clone());
+            }
+            (Some(view), None) => {
+                for m in monitors {
+                    config.monitor_views.insert(m.name.clone(), view.clone());
                 }
-
-                if row.anim_start.is_none() {
-                     row.prev_time_str = row.time_str.clone();
-                     row.prev_ampm = row.ampm.clone();
-                     row.prev_day = row.day.clone();
-
-                     row.time_str = time_str;
-                     row.ampm = ampm_str.to_string();
-                     row.day = day_str;
-
-                     row.anim_start = Some(get_time());
+            }
+            (None, Some(name)) => {
+                // Keep what that monitor normally shows and blank the rest
+                let view = match config.monitor_views.get(name) {
+                    Some(ViewType::Off) | None => ViewType::Clock,
+                    Some(view) => view.clone(),
+                };
+                for m in monitors {
+                    config.monitor_views.insert(m.name.clone(), ViewType::Off);
                 }
+                config.monitor_views.insert(name.clone(), view);
             }
+            (None, None) => {}
         }
     }
 }
 
+/// Gives monitors missing from `monitor_views` a default: on a fresh config the primary
+/// monitor shows the clock, otherwise monitors the config doesn't know yet start off.
+fn fill_monitor_views(config: &mut AppConfig, monitors: &[windows_utils::MonitorInfo]) {
+    let fresh = config.monitor_views.is_empty();
+    for m in monitors {
+        if config.monitor_views.contains_key(&m.name) {
+            continue;
+        }
+        let view = if fresh && m.is_primary { ViewType::Clock } else { ViewType::Off };
+        config.monitor_views.insert(m.name.clone(), view);
+    }
+}
 
-#[derive(PartialEq)]
-enum AppMode {
-    Clock { preview: bool },
-    Setup,
+/// The monitor whose view is drawn when the window shows just one: the primary if it's on,
+/// else the first one that's on.
+fn shown_monitor<'a>(config: &AppConfig, monitors: &'a [windows_utils::MonitorInfo]) -> Option<&'a windows_utils::MonitorInfo> {
+    let on = |m: &&windows_utils::MonitorInfo| {
+        config.monitor_views.get(&m.name).is_some_and(|v| *v != ViewType::Off)
+    };
+    monitors.iter().filter(|m| m.is_primary).find(on)
+        .or_else(|| monitors.iter().find(on))
 }
 
+/// The view drawn when the window shows just one, or the clock if every monitor is off.
+fn single_view(config: &AppConfig, monitors: &[windows_utils::MonitorInfo]) -> ViewType {
+    shown_monitor(config, monitors)
+        .and_then(|m| config.monitor_views.get(&m.name).cloned())
+        .unwrap_or(ViewType::Clock)
+}
+
+/// Lets us find our own window on X11.
+const WM_CLASS: &str = "rust_flip_clock";
+
 fn window_conf() -> Conf {
     Conf {
         window_title: "Flip Clock".to_owned(),
         high_dpi: true,
         window_width: 1024,
         window_height: 768,
+        platform: miniquad::conf::Platform {
+            linux_wm_class: WM_CLASS,
+            ..Default::default()
+        },
         ..Default::default()
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut mode = AppMode::Setup;
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let cli = match Cli::parse(&args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(path) = &cli.config {
+        config::set_config_path(path.clone());
+    }
+    if cli.safe_mode {
+        config::enable_safe_mode();
+    }
+
+    match &cli.command {
+        Command::Help => println!("{}", cli::USAGE),
+        // `ctl` talks to a running clock and never opens a window
+        Command::Ctl(words) => std::process::exit(run_ctl(words)),
+        Command::CheckConfig => std::process::exit(check_config()),
+        _ => macroquad::Window::from_config(window_conf(), run(cli)),
+    }
+}
 
-    if args.len() > 1 {
-        let arg = args[1].to_lowercase();
-        if arg.starts_with("/s") {
-            mode = AppMode::Clock { preview: false };
-        } else if arg.starts_with("/c") {
-             mode = AppMode::Setup;
-        } else if arg.starts_with("/p") {
-             mode = AppMode::Clock { preview: true };
+/// `--check-config`: reports whether the config files load, without touching them.
+fn check_config() -> i32 {
+    match SystemConfig::load() {
+        Ok(SystemConfig { path: Some(path), .. }) => println!("{} is valid", path.display()),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    }
+    let path = config::get_config_path();
+    match load_config() {
+        Ok(_) if !path.exists() => {
+            println!("{} does not exist; the defaults are used", path.display());
+            0
+        }
+        Ok(_) => {
+            let portable = if config::is_portable() { " (portable mode)" } else { "" };
+            println!("{} is valid{}", path.display(), portable);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
         }
     }
+}
+
+fn run_ctl(args: &[String]) -> i32 {
+    if args.is_empty() || args[0] == "help" || args[0] == "--help" {
+        println!("Usage: {} ctl <command>\n\n{}", env!("CARGO_PKG_NAME"), control::USAGE);
+        return if args.is_empty() { 2 } else { 0 };
+    }
+
+    let line = args.join(" ");
+    if let Err(e) = ControlCommand::parse(&line) {
+        eprintln!("{}\n\n{}", e, control::USAGE);
+        return 2;
+    }
+    match control::send(&line) {
+        Ok(reply) => {
+            if !reply.is_empty() {
+                println!("{}", reply);
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+async fn run(cli: Cli) {
+    let mut mode = match cli.command {
+        Command::Screensaver => AppMode::Clock { preview: false },
+        Command::Preview { parent: Some(parent) } => AppMode::Embedded { parent },
+        Command::Preview { parent: None } => AppMode::Clock { preview: true },
+        Command::Windowed => AppMode::Windowed,
+        Command::Desk => AppMode::Desk,
+        Command::Hack { window } => AppMode::Hack {
+            // xscreensaver hands its window over in the environment as well
+            window: window.or_else(|| env::var("XSCREENSAVER_WINDOW").ok().and_then(|id| cli::parse_window_id(&id))),
+        },
+        _ => AppMode::Setup,
+    };
+    let overrides = ViewOverrides { view: cli.view, monitor: cli.monitor, ..Default::default() };
+
+    let time = time_source::from_env();
 
     // Load font once
     let font_path = "assets/fonts/Roboto-Bold.ttf";
@@ -365,17 +696,17 @@ async fn main() {
 
     loop {
         match mode {
-            AppMode::Clock { preview } => {
-                run_clock(preview, font.as_ref()).await;
-                if preview {
-                    mode = AppMode::Setup;
+            AppMode::Setup => {
+                if let Some(next_mode) = run_setup(font.as_ref(), time.clone()).await {
+                    mode = next_mode;
                 } else {
                     break;
                 }
             },
-            AppMode::Setup => {
-                if let Some(next_mode) = run_setup(font.as_ref()).await {
-                    mode = next_mode;
+            _ => {
+                run_clock(&mode, font.as_ref(), time.clone(), &overrides).await;
+                if mode == (AppMode::Clock { preview: true }) {
+                    mode = AppMode::Setup;
                 } else {
                     break;
                 }
@@ -387,36 +718,38 @@ async fn main() {
 #[derive(PartialEq)]
 enum SetupTab {
     General,
+    Board,
+    Messages,
     Layout,
     Theme,
+    Hotkeys,
+    Profiles,
 }
 
-async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
-    let mut config = load_config();
+async fn run_setup(font: Option<&Font>, time: Rc<dyn TimeSource>) -> Option<AppMode> {
+    let (mut config, mut config_reset) = load_config_or_reset();
+    // Its errors already reset `config`; locks only need the settings when it loads
+    let system = SystemConfig::load().unwrap_or_default();
+    let mut saver = ConfigSaver::new();
+    // Closing the window must not lose a pending save
+    prevent_quit();
     let monitors = windows_utils::get_monitors();
     let mut active_tab = SetupTab::General; // Default to General for monitor selection
 
-    // Migrate old selected_monitor to new map if map is empty
-    if config.monitor_views.is_empty() {
-        for m in &monitors {
-            // Default logic: If selected_monitor matches, set to Clock, else Off (or Clock if primary)
-            // If selected_monitor was empty, primary gets Clock.
-            let should_be_clock = if config.selected_monitor.is_empty() {
-                m.is_primary
-            } else {
-                m.name == config.selected_monitor
-            };
-
-            if should_be_clock {
-                config.monitor_views.insert(m.name.clone(), ViewType::Clock);
-            } else {
-                config.monitor_views.insert(m.name.clone(), ViewType::Off);
-            }
-        }
+    if identify_monitors(&mut config, &monitors) {
+        saver.changed();
     }
-
-    let mut install_status = String::new();
-    let mut clock_state = ClockState::new();
+    fill_monitor_views(&mut config, &monitors);
+
+    let mut install_options = installer::InstallOptions::default();
+    let mut install_state = installer::status();
+    let mut install_message: Option<Result<String, String>> = None;
+    let mut dry_run: Option<Vec<installer::Change>> = None;
+    // While a profile is edited, `config` is a working copy with the profile laid over it
+    let mut editing: Option<ProfileEdit> = None;
+    let mut profile_name = String::new();
+    let mut profile_error: Option<String> = None;
+    let mut views = ViewStates::new(time.clone(), &config);
 
     // Preview Render Target
     let preview_width = 400;
@@ -424,14 +757,23 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
     let preview_target = render_target(preview_width as u32, preview_height as u32);
     preview_target.texture.set_filter(FilterMode::Linear);
 
+    // Low-res target for the pixelated preview
+    let pixel_w = preview_width / 4;
+    let pixel_h = preview_height / 4;
+    let pixel_target = render_target(pixel_w as u32, pixel_h as u32);
+    pixel_target.texture.set_filter(FilterMode::Nearest);
+
     loop {
         // Update Time
-        clock_state.update(config.use_12h_format);
+        views.update(&config);
 
         // --- Render Preview Clock to Texture ---
-        // For preview, we just show the Clock face regardless of settings for simplicity,
-        // or we could show the Departure Board if that's selected for a monitor.
-        // Let's just show the standard Clock Face in the sidebar preview for now.
+        // The preview shows the standard Clock Face, or a board while its tab is open.
+        let preview_view = match active_tab {
+            SetupTab::Board => ViewType::DepartureBoard,
+            SetupTab::Messages => ViewType::MessageBoard,
+            _ => ViewType::Clock,
+        };
         {
             if config.pixelated {
                 // 1. Render to tiny target
@@ -446,7 +788,7 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
                 let bg = mq_color_from_config(config.bg_color);
                 clear_background(bg);
                 let rect = Rect::new(0.0, 0.0, pixel_w as f32, pixel_h as f32);
-                draw_clock_face(&config, &mut time_state, rect, font, true);
+                views.draw(&preview_view, &config, rect, font, true);
 
                 set_default_camera();
 
@@ -494,15 +836,17 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
 
                 // Draw Clock
                 let rect = Rect::new(0.0, 0.0, preview_width as f32, preview_height as f32);
-                draw_clock_face(&config, &mut time_state, rect, font, true);
+                views.draw(&preview_view, &config, rect, font, true);
 
             set_default_camera();
+            }
         }
 
         clear_background(BLACK);
 
         let mut next_mode: Option<AppMode> = None;
         let mut exit_setup = false;
+        let mut dismiss_reset = false;
 
         egui_macroquad::ui(|ctx| {
              // Dark Theme Setup
@@ -537,13 +881,21 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
                      };
 
                      if let Some(t) = nav_btn(ui, "General / Monitors", SetupTab::General, &active_tab) { active_tab = t; }
+                     if let Some(t) = nav_btn(ui, "Departure Board", SetupTab::Board, &active_tab) { active_tab = t; }
+                     if let Some(t) = nav_btn(ui, "Message Board", SetupTab::Messages, &active_tab) { active_tab = t; }
                      if let Some(t) = nav_btn(ui, "Layout & Size", SetupTab::Layout, &active_tab) { active_tab = t; }
                      if let Some(t) = nav_btn(ui, "Theme & Color", SetupTab::Theme, &active_tab) { active_tab = t; }
+                     if let Some(t) = nav_btn(ui, "Hotkeys", SetupTab::Hotkeys, &active_tab) { active_tab = t; }
+                     if let Some(t) = nav_btn(ui, "Profiles", SetupTab::Profiles, &active_tab) { active_tab = t; }
 
                      ui.add_space(40.0);
 
                      // PREVIEW
-                     ui.label("PREVIEW (Clock Mode)");
+                     ui.label(match preview_view {
+                         ViewType::DepartureBoard => "PREVIEW (Departure Board)",
+                         ViewType::MessageBoard => "PREVIEW (Message Board)",
+                         _ => "PREVIEW (Clock Mode)",
+                     });
 
                      // Retrieve raw OpenGL Texture ID from Miniquad
                      let gl = unsafe { get_internal_gl() };
@@ -567,34 +919,77 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
                      });
                  });
 
+             if let Some(reset) = &config_reset {
+                 egui::TopBottomPanel::top("config_reset").show(ctx, |ui| {
+                     ui.add_space(6.0);
+                     ui.horizontal(|ui| {
+                         ui.label(egui::RichText::new("Settings were reset to the defaults.").strong().color(egui::Color32::from_rgb(255, 190, 90)));
+                         if ui.button("Dismiss").clicked() {
+                             dismiss_reset = true;
+                         }
+                     });
+                     ui.label(reset.error.to_string());
+                     if let Some(backup) = &reset.backup {
+                         ui.label(format!("The old file was kept as {}", backup.display()));
+                     } else if let config::ConfigError::TooNew { .. } = reset.error {
+                         ui.label("The file was left in place, and changes here won't be saved over it.");
+                     }
+                     ui.add_space(6.0);
+                 });
+             }
+
              // BOTTOM BAR (Action Bar)
              egui::TopBottomPanel::bottom("bottom_bar")
                  .min_height(60.0)
                  .show(ctx, |ui| {
                      ui.horizontal(|ui| {
                          ui.add_space(20.0);
-                         if !install_status.is_empty() {
-                             ui.label(egui::RichText::new(&install_status).color(egui::Color32::GREEN));
-                         } else {
-                             ui.label("Ready to install");
+                         if let Some(e) = &saver.last_error {
+                             ui.label(egui::RichText::new(e).color(egui::Color32::LIGHT_RED));
+                             ui.separator();
+                         }
+                         match &install_message {
+                             Some(Ok(msg)) => { ui.label(egui::RichText::new(msg).color(egui::Color32::GREEN)); }
+                             Some(Err(e)) => { ui.label(egui::RichText::new(format!("Error: {}", e)).color(egui::Color32::LIGHT_RED)); }
+                             None => { ui.label(&install_state.summary); }
                          }
 
                          ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                              ui.add_space(20.0);
 
-                             if ui.button("Install Screensaver")
-                                 .on_hover_text("Copies the screensaver to C:\\Windows\\System32 so it appears in Windows Screen Saver Settings.")
-                                 .clicked()
-                             {
-                                 if let Ok(exe_path) = env::current_exe() {
-                                     let target = Path::new("C:\\Windows\\System32\\rust_flip_clock.scr");
-                                     match std::fs::copy(&exe_path, target) {
-                                         Ok(_) => install_status = "Successfully installed to System32!".to_string(),
-                                         Err(e) => install_status = format!("Error: {}", e),
+                             let mut plan: Option<Result<Vec<installer::Change>, String>> = None;
+                             ui.add_enabled_ui(installer::supported(), |ui| {
+                                 if ui.add_enabled(install_state.installed, egui::Button::new("Uninstall")).clicked() {
+                                     plan = Some(installer::uninstall_plan());
+                                 }
+                                 if ui.button("Dry Run")
+                                     .on_hover_text("Lists the files installing would change, without changing them.")
+                                     .clicked()
+                                 {
+                                     match installer::install_plan(&install_options) {
+                                         Ok(changes) => dry_run = Some(changes),
+                                         Err(e) => install_message = Some(Err(e)),
                                      }
+                                 }
+                                 let hover = if cfg!(windows) {
+                                     "Copies the screensaver into System32 so it appears in Windows Screen Saver Settings. Needs administrator rights."
                                  } else {
-                                     install_status = "Could not locate current executable.".to_string();
+                                     "Adds the clock to the XScreenSaver program list and the application menu."
+                                 };
+                                 if ui.button("Install Screensaver").on_hover_text(hover).clicked() {
+                                     plan = Some(installer::install_plan(&install_options));
                                  }
+                                 if cfg!(target_os = "linux") {
+                                     ui.checkbox(&mut install_options.systemd_unit, "Start XScreenSaver at login");
+                                 }
+                             });
+
+                             if let Some(plan) = plan {
+                                 install_message = Some(plan.and_then(|changes| {
+                                     installer::apply(&changes)?;
+                                     Ok(format!("Done ({} changes)", changes.len()))
+                                 }));
+                                 install_state = installer::status();
                              }
 
                              ui.add_space(10.0);
@@ -609,10 +1004,38 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
                      });
                  });
 
+             if let Some(changes) = &dry_run {
+                 let mut open = true;
+                 egui::Window::new("Install Dry Run")
+                     .open(&mut open)
+                     .collapsible(false)
+                     .show(ctx, |ui| {
+                         if changes.is_empty() {
+                             ui.label("Nothing to change.");
+                         }
+                         for change in changes {
+                             ui.label(change.to_string());
+                         }
+                     });
+                 if !open {
+                     dry_run = None;
+                 }
+             }
+
              // MAIN CONTENT
              egui::CentralPanel::default().show(ctx, |ui| {
                  egui::ScrollArea::vertical().show(ui, |ui| {
                      ui.add_space(20.0);
+                     if let Some(edit) = &editing {
+                         ui.label(egui::RichText::new(format!("Editing profile \"{}\": changes apply while it's scheduled.", edit.name))
+                             .color(egui::Color32::from_rgb(255, 190, 90)));
+                         ui.add_space(10.0);
+                     }
+                     if let Some(path) = system.path.as_ref().filter(|_| system.has_locks()) {
+                         ui.label(egui::RichText::new(format!("Greyed-out settings are locked by {}.", path.display()))
+                             .color(egui::Color32::from_rgb(255, 190, 90)));
+                         ui.add_space(10.0);
+                     }
                      match active_tab {
                          SetupTab::General => {
                              ui.heading("Monitor Configuration");
@@ -627,68 +1050,390 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
                                     let current_view = config.monitor_views.get(&m.name).cloned().unwrap_or(ViewType::Off);
                                     let mut selected_view = current_view.clone();
 
-                                    egui::ComboBox::from_id_salt(&m.name)
-                                        .selected_text(match selected_view {
-                                            ViewType::Clock => "Flip Clock",
-                                            ViewType::DepartureBoard => "Departure Board",
-                                            ViewType::Off => "Off (Black)",
-                                        })
-                                        .show_ui(ui, |ui| {
-                                            ui.selectable_value(&mut selected_view, ViewType::Clock, "Flip Clock");
-                                            ui.selectable_value(&mut selected_view, ViewType::DepartureBoard, "Departure Board");
-                                            ui.selectable_value(&mut selected_view, ViewType::Off, "Off (Black)");
-                                        });
+                                    locked_ui(ui, &system, &["monitor_views"], |ui| {
+                                        egui::ComboBox::from_id_salt(&m.name)
+                                            .selected_text(match selected_view {
+                                                ViewType::Clock => "Flip Clock",
+                                                ViewType::DepartureBoard => "Departure Board",
+                                                ViewType::MessageBoard => "Message Board",
+                                                ViewType::Off => "Off (Black)",
+                                            })
+                                            .show_ui(ui, |ui| {
+                                                ui.selectable_value(&mut selected_view, ViewType::Clock, "Flip Clock");
+                                                ui.selectable_value(&mut selected_view, ViewType::DepartureBoard, "Departure Board");
+                                                ui.selectable_value(&mut selected_view, ViewType::MessageBoard, "Message Board");
+                                                ui.selectable_value(&mut selected_view, ViewType::Off, "Off (Black)");
+                                            });
+                                    });
 
                                     if selected_view != current_view {
                                         config.monitor_views.insert(m.name.clone(), selected_view);
-                                        save_config(&config);
+                                        saver.changed();
                                     }
+
+                                    let mut settings = config.monitor_settings.get(&m.name).cloned().unwrap_or_default();
+                                    let title = if settings.is_empty() { "Overrides".to_string() } else { "Overrides (in use)".to_string() };
+                                    locked_ui(ui, &system, &["monitor_settings"], |ui| {
+                                        egui::CollapsingHeader::new(title).id_salt(("overrides", &m.name)).show(ui, |ui| {
+                                            if monitor_settings_ui(ui, &mut settings, &config) {
+                                                if settings.is_empty() {
+                                                    config.monitor_settings.remove(&m.name);
+                                                } else {
+                                                    config.monitor_settings.insert(m.name.clone(), settings);
+                                                }
+                                                saver.changed();
+                                            }
+                                        });
+                                    });
                                  });
                                  ui.add_space(5.0);
                              }
 
+                             let orphans = monitor_identity::orphaned(&config, &monitors);
+                             if !orphans.is_empty() {
+                                 ui.add_space(10.0);
+                                 ui.label(egui::RichText::new("Disconnected Monitors").strong());
+                                 ui.label("Settings kept for monitors that aren't connected. If one is connected under a new name, reassign it.");
+                                 let mut reassign: Option<(String, String)> = None;
+                                 let mut forget: Option<String> = None;
+                                 for key in orphans {
+                                     ui.group(|ui| {
+                                         locked_ui(ui, &system, &["monitor_views", "monitor_settings", "known_monitors"], |ui| {
+                                             ui.horizontal(|ui| {
+                                                 let details = config.known_monitors.get(&key)
+                                                     .map_or_else(|| "no details recorded".to_string(), |id| id.describe());
+                                                 ui.label(format!("{} ({})", key, details));
+                                                 ui.menu_button("Reassign to", |ui| {
+                                                     for m in &monitors {
+                                                         if ui.button(&m.name).clicked() {
+                                                             reassign = Some((key.clone(), m.name.clone()));
+                                                             ui.close_menu();
+                                                         }
+                                                     }
+                                                 });
+                                         });
+                                             if ui.button("Forget").clicked() {
+                                                 forget = Some(key.clone());
+                                             }
+                                         });
+                                     });
+                                 }
+                                 if let Some((from, to)) = reassign {
+                                     monitor_identity::reassign(&mut config, &from, &to);
+                                     saver.changed();
+                                 }
+                                 if let Some(key) = forget {
+                                     monitor_identity::forget(&mut config, &key);
+                                     saver.changed();
+                                 }
+                             }
+
                              ui.add_space(20.0);
                              ui.separator();
                              ui.add_space(20.0);
 
                              ui.heading("Clock Behavior");
-                             if ui.checkbox(&mut config.use_12h_format, "12-Hour Format").changed() { save_config(&config); }
-                             if ui.checkbox(&mut config.show_seconds, "Show Seconds").changed() { save_config(&config); }
+                             if locked_ui(ui, &system, &["use_12h_format"], |ui| ui.checkbox(&mut config.use_12h_format, "12-Hour Format").changed()) { saver.changed(); }
+                             if locked_ui(ui, &system, &["show_seconds"], |ui| ui.checkbox(&mut config.show_seconds, "Show Seconds").changed()) { saver.changed(); }
+
+                             ui.add_space(20.0);
+                             ui.heading("Desk Clock");
+                             ui.label("Start with --desk for a clock window that stays open. A click switches the view; see Hotkeys for the keys.");
+                             if locked_ui(ui, &system, &["desk_borderless"], |ui| ui.checkbox(&mut config.desk_borderless, "Borderless Window").changed()) { saver.changed(); }
+                             if locked_ui(ui, &system, &["desk_always_on_top"], |ui| ui.checkbox(&mut config.desk_always_on_top, "Always on Top").changed()) { saver.changed(); }
+
+                             ui.add_space(20.0);
+                             ui.heading("Screensaver Exit");
+                             locked_ui(ui, &system, &["exit_policy"], |ui| {
+                                 let mut changed = false;
+                                 let policy = &mut config.exit_policy;
+                                 changed |= ui.checkbox(&mut policy.kiosk, "Kiosk Mode")
+                                     .on_hover_text("Ignore keys, clicks, movement and touch. Only the exit chord closes the screensaver.")
+                                     .changed();
+                                 ui.add_enabled_ui(!policy.kiosk, |ui| {
+                                     ui.horizontal(|ui| {
+                                         changed |= ui.checkbox(&mut policy.on_key, "Any Key").changed();
+                                         changed |= ui.checkbox(&mut policy.on_click, "Click").changed();
+                                         changed |= ui.checkbox(&mut policy.on_mouse_move, "Mouse Movement").changed();
+                                         changed |= ui.checkbox(&mut policy.on_touch, "Touch").changed();
+                                     });
+                                     ui.label("Movement Threshold (px)");
+                                     changed |= ui.add(egui::Slider::new(&mut policy.move_threshold, 1.0..=200.0)).changed();
+                                 });
+                                 ui.label("Grace Period (s)");
+                                 changed |= ui.add(egui::Slider::new(&mut policy.grace_secs, 0.0..=5.0)).changed();
+                                 ui.horizontal(|ui| {
+                                     ui.label("Exit Chord");
+                                     changed |= ui.add(egui::TextEdit::singleline(&mut policy.chord).hint_text("e.g. Ctrl+Shift+Q").desired_width(140.0)).changed();
+                                     ui.label("PIN");
+                                     changed |= ui.add(egui::TextEdit::singleline(&mut policy.pin).password(true).desired_width(80.0)).changed();
+                                 });
+                                 let chord_error = match policy.chord.trim() {
+                                     "" => None,
+                                     chord => chord.parse::<input::KeyChord>().err(),
+                                 };
+                                 if let Some(e) = chord_error {
+                                     ui.label(egui::RichText::new(e).color(egui::Color32::LIGHT_RED));
+                                 } else if policy.kiosk && policy.chord.trim().is_empty() {
+                                     ui.label(egui::RichText::new("Without an exit chord, kiosk mode can only be ended by closing the program.").color(egui::Color32::LIGHT_RED));
+                                 }
+                                 if changed { saver.changed(); }
+                             });
                          },
-                         SetupTab::Layout => {
-                             ui.heading("Dimensions");
+                         SetupTab::Board => {
+                             ui.heading("Cities");
+                             ui.label("Rows are shown top to bottom. Zones use IANA names, e.g. America/New_York.");
                              ui.add_space(10.0);
 
-                             ui.label("Overall Scale (%)");
-                             // Scale 20% to 100%
-                             let mut scale_pct = config.scale * 100.0;
-                             if ui.add(egui::Slider::new(&mut scale_pct, 20.0..=100.0)).changed() {
-                                 config.scale = scale_pct / 100.0;
-                                 save_config(&config);
-                             }
+                             let mut changed = false;
+                             locked_ui(ui, &system, &["board_cities"], |ui| {
+                                 let mut move_up: Option<usize> = None;
+                                 let mut remove: Option<usize> = None;
+                                 let city_count = config.board_cities.len();
+
+                                 for (i, city) in config.board_cities.iter_mut().enumerate() {
+                                     ui.group(|ui| {
+                                         ui.horizontal(|ui| {
+                                             ui.label("Name");
+                                             changed |= ui.add(egui::TextEdit::singleline(&mut city.name).desired_width(140.0)).changed();
+                                             ui.label("Zone");
+                                             changed |= ui.add(egui::TextEdit::singleline(&mut city.zone).desired_width(160.0)).changed();
+                                             changed |= ui.checkbox(&mut city.show_abbreviation, "Abbrev.").changed();
+
+                                             if ui.add_enabled(i > 0, egui::Button::new("⬆")).clicked() { move_up = Some(i); }
+                                             if ui.add_enabled(i + 1 < city_count, egui::Button::new("⬇")).clicked() { move_up = Some(i + 1); }
+                                             if ui.button("Remove").clicked() { remove = Some(i); }
+                                         });
+                                         if city.zone.parse::<chrono_tz::Tz>().is_err() {
+                                             ui.label(egui::RichText::new("Unknown time zone, showing UTC").color(egui::Color32::LIGHT_RED));
+                                         }
+                                     });
+                                 }
 
-                             ui.label("Card Spacing (%)");
-                             let mut spacing_pct = config.spacing * 100.0;
-                             if ui.add(egui::Slider::new(&mut spacing_pct, 0.0..=10.0)).changed() {
-                                 config.spacing = spacing_pct / 100.0;
-                                 save_config(&config);
+                                 if let Some(i) = move_up {
+                                     config.board_cities.swap(i - 1, i);
+                                     changed = true;
+                                 }
+                                 if let Some(i) = remove {
+                                     config.board_cities.remove(i);
+                                     changed = true;
+                                 }
+
+                                 ui.add_space(10.0);
+                                 if ui.button("Add City").clicked() {
+                                     config.board_cities.push(BoardCity::new("NEW CITY", "UTC", false));
+                                     changed = true;
+                                 }
+                             });
+
+                             ui.add_space(20.0);
+                             ui.separator();
+                             ui.add_space(20.0);
+
+                             ui.heading("Columns");
+                             ui.label("Shown left to right after the city name. Time follows the 12-Hour Format setting.");
+                             ui.add_space(10.0);
+
+                             locked_ui(ui, &system, &["board_columns"], |ui| {
+                                 // Enabled columns in display order, then the disabled ones
+                                 let mut listed = config.board_columns.clone();
+                                 listed.extend(BoardColumn::ALL.iter().filter(|c| !config.board_columns.contains(c)));
+
+                                 let mut column_up: Option<usize> = None;
+                                 let enabled_count = config.board_columns.len();
+                                 for column in listed {
+                                     let position = config.board_columns.iter().position(|c| *c == column);
+                                     ui.horizontal(|ui| {
+                                         let mut enabled = position.is_some();
+                                         if ui.checkbox(&mut enabled, column.label()).changed() {
+                                             if enabled {
+                                                 config.board_columns.push(column);
+                                             } else {
+                                                 config.board_columns.retain(|c| *c != column);
+                                             }
+                                             changed = true;
+                                         }
+                                         if let Some(i) = position {
+                                             if ui.add_enabled(i > 0, egui::Button::new("⬆")).clicked() { column_up = Some(i); }
+                                             if ui.add_enabled(i + 1 < enabled_count, egui::Button::new("⬇")).clicked() { column_up = Some(i + 1); }
+                                         }
+                                     });
+                                 }
+                                 if let Some(i) = column_up {
+                                     config.board_columns.swap(i - 1, i);
+                                     changed = true;
+                                 }
+                             });
+
+                             ui.add_space(10.0);
+                             changed |= locked_ui(ui, &system, &["board_show_seconds"], |ui| ui.checkbox(&mut config.board_show_seconds, "Show Seconds on Board").changed());
+
+                             ui.add_space(20.0);
+                             ui.heading("Split-Flap Animation");
+                             locked_ui(ui, &system, &["flap_roll"], |ui| {
+                                 changed |= ui.checkbox(&mut config.flap_roll, "Roll through the alphabet")
+                                     .on_hover_text("Each flap steps through every character until it reaches the new one, like a real Solari board.")
+                                     .changed();
+                             });
+
+                             locked_ui(ui, &system, &["flap_step_ms"], |ui| {
+                                 ui.add_enabled_ui(config.flap_roll, |ui| {
+                                     ui.label("Step Time (ms per flap)");
+                                     changed |= ui.add(egui::Slider::new(&mut config.flap_step_ms, 10..=200)).changed();
+                                 });
+                             });
+
+                             locked_ui(ui, &system, &["flap_stagger"], |ui| {
+                                 ui.label("Staggered Start");
+                                 egui::ComboBox::from_id_salt("flap_stagger")
+                                     .selected_text(match config.flap_stagger {
+                                         FlapStagger::None => "All at once",
+                                         FlapStagger::Column => "Per column",
+                                         FlapStagger::Row => "Per row",
+                                     })
+                                     .show_ui(ui, |ui| {
+                                         changed |= ui.selectable_value(&mut config.flap_stagger, FlapStagger::None, "All at once").changed();
+                                         changed |= ui.selectable_value(&mut config.flap_stagger, FlapStagger::Column, "Per column").changed();
+                                         changed |= ui.selectable_value(&mut config.flap_stagger, FlapStagger::Row, "Per row").changed();
+                                     });
+                             });
+                             locked_ui(ui, &system, &["flap_stagger_ms"], |ui| {
+                                 ui.add_enabled_ui(config.flap_stagger != FlapStagger::None, |ui| {
+                                     ui.label("Stagger Delay (ms)");
+                                     changed |= ui.add(egui::Slider::new(&mut config.flap_stagger_ms, 0..=200)).changed();
+                                 });
+                             });
+
+                             if changed {
+                                 saver.changed();
                              }
+                         },
+                         SetupTab::Messages => {
+                             ui.heading("Grid");
+                             let mut changed = false;
+                             locked_ui(ui, &system, &["message_rows"], |ui| {
+                                 ui.label("Rows");
+                                 changed |= ui.add(egui::Slider::new(&mut config.message_rows, 1..=12)).changed();
+                             });
+                             locked_ui(ui, &system, &["message_columns"], |ui| {
+                                 ui.label("Characters per Row");
+                                 changed |= ui.add(egui::Slider::new(&mut config.message_columns, 8..=40)).changed();
+                             });
+
+                             ui.add_space(20.0);
+                             ui.separator();
+                             ui.add_space(20.0);
+
+                             ui.heading("Messages");
+                             ui.label("Shown in order, each for its dwell time, then repeated. Long lines wrap at spaces.");
+                             ui.add_space(10.0);
+
+                             locked_ui(ui, &system, &["messages"], |ui| {
+                                 let mut move_up: Option<usize> = None;
+                                 let mut remove: Option<usize> = None;
+                                 let message_count = config.messages.len();
+
+                                 for (i, message) in config.messages.iter_mut().enumerate() {
+                                     ui.group(|ui| {
+                                         changed |= ui.add(egui::TextEdit::multiline(&mut message.text).desired_rows(2).desired_width(f32::INFINITY)).changed();
+                                         ui.horizontal(|ui| {
+                                             ui.label("Dwell (s)");
+                                             changed |= ui.add(egui::DragValue::new(&mut message.dwell_secs).range(1..=3600)).changed();
+
+                                             egui::ComboBox::from_id_salt(("message_align", i))
+                                                 .selected_text(match message.align {
+                                                     TextAlign::Left => "Left",
+                                                     TextAlign::Center => "Center",
+                                                     TextAlign::Right => "Right",
+                                                 })
+                                                 .show_ui(ui, |ui| {
+                                                     changed |= ui.selectable_value(&mut message.align, TextAlign::Left, "Left").changed();
+                                                     changed |= ui.selectable_value(&mut message.align, TextAlign::Center, "Center").changed();
+                                                     changed |= ui.selectable_value(&mut message.align, TextAlign::Right, "Right").changed();
+                                                 });
+
+                                             if ui.add_enabled(i > 0, egui::Button::new("⬆")).clicked() { move_up = Some(i); }
+                                             if ui.add_enabled(i + 1 < message_count, egui::Button::new("⬇")).clicked() { move_up = Some(i + 1); }
+                                             if ui.button("Remove").clicked() { remove = Some(i); }
+                                         });
+                                     });
+                                 }
+
+                                 if let Some(i) = move_up {
+                                     config.messages.swap(i - 1, i);
+                                     changed = true;
+                                 }
+                                 if let Some(i) = remove {
+                                     config.messages.remove(i);
+                                     changed = true;
+                                 }
 
-                             ui.label("Corner Radius (px)");
-                             if ui.add(egui::Slider::new(&mut config.corner_radius, 0.0..=20.0)).changed() {
-                                 save_config(&config);
+                                 ui.add_space(10.0);
+                                 if ui.button("Add Message").clicked() {
+                                     config.messages.push(BoardMessage::new("NEW MESSAGE"));
+                                     changed = true;
+                                 }
+                             });
+
+                             if changed {
+                                 saver.changed();
                              }
+                         },
+                         SetupTab::Layout => {
+                             ui.heading("Dimensions");
+                             ui.add_space(10.0);
+
+                             locked_ui(ui, &system, &["scale"], |ui| {
+                                 ui.label("Overall Scale (%)");
+                                 // Scale 20% to 100%
+                                 let mut scale_pct = config.scale * 100.0;
+                                 if ui.add(egui::Slider::new(&mut scale_pct, 20.0..=100.0)).changed() {
+                                     config.scale = scale_pct / 100.0;
+                                     saver.changed();
+                                 }
+                             });
+
+                             locked_ui(ui, &system, &["spacing"], |ui| {
+                                 ui.label("Card Spacing (%)");
+                                 let mut spacing_pct = config.spacing * 100.0;
+                                 if ui.add(egui::Slider::new(&mut spacing_pct, 0.0..=10.0)).changed() {
+                                     config.spacing = spacing_pct / 100.0;
+                                     saver.changed();
+                                 }
+                             });
+
+                             locked_ui(ui, &system, &["corner_radius"], |ui| {
+                                 ui.label("Corner Radius (px)");
+                                 if ui.add(egui::Slider::new(&mut config.corner_radius, 0.0..=20.0)).changed() {
+                                     saver.changed();
+                                 }
+                             });
 
                              ui.add_space(20.0);
                              ui.heading("Rendering Style");
-                             if ui.checkbox(&mut config.pixelated, "Retro Pixelated Mode").changed() {
-                                 save_config(&config);
-                             }
+                             locked_ui(ui, &system, &["pixelated"], |ui| {
+                                 if ui.checkbox(&mut config.pixelated, "Retro Pixelated Mode").changed() {
+                                     saver.changed();
+                                 }
+                             });
                          },
                          SetupTab::Theme => {
                              ui.heading("Colors");
                              ui.add_space(10.0);
 
+                             locked_ui(ui, &system, &["bg_color", "card_color", "text_color"], |ui| {
+                                 ui.horizontal(|ui| {
+                                     ui.label("Presets");
+                                     for theme in &config::THEMES {
+                                         if ui.button(theme.name).clicked() {
+                                             config.apply_theme(theme);
+                                             saver.changed();
+                                         }
+                                     }
+                                 });
+                             });
+                             ui.add_space(10.0);
+
                              fn color_edit(ui: &mut egui::Ui, label: &str, color: &mut [f32; 3]) -> bool {
                                  let mut rgb = [color[0], color[1], color[2]];
                                  let changed = ui.color_edit_button_rgb(&mut rgb).changed();
@@ -700,20 +1445,55 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
                              }
 
                              ui.horizontal(|ui| {
-                                 if color_edit(ui, "Background", &mut config.bg_color) { save_config(&config); }
+                                 if locked_ui(ui, &system, &["bg_color"], |ui| color_edit(ui, "Background", &mut config.bg_color)) { saver.changed(); }
                              });
                              ui.horizontal(|ui| {
-                                 if color_edit(ui, "Card Background", &mut config.card_color) { save_config(&config); }
+                                 if locked_ui(ui, &system, &["card_color"], |ui| color_edit(ui, "Card Background", &mut config.card_color)) { saver.changed(); }
                              });
                              ui.horizontal(|ui| {
-                                 if color_edit(ui, "Text / Digits", &mut config.text_color) { save_config(&config); }
+                                 if locked_ui(ui, &system, &["text_color"], |ui| color_edit(ui, "Text / Digits", &mut config.text_color)) { saver.changed(); }
                              });
 
                              ui.add_space(20.0);
                              ui.heading("Animation");
-                             ui.label("Flip Duration (ms)");
-                             if ui.add(egui::Slider::new(&mut config.animation_speed, 100..=2000)).changed() {
-                                 save_config(&config);
+                             locked_ui(ui, &system, &["animation_speed"], |ui| {
+                                 ui.label("Flip Duration (ms)");
+                                 if ui.add(egui::Slider::new(&mut config.animation_speed, 100..=2000)).changed() {
+                                     saver.changed();
+                                 }
+                             });
+                         }
+                         SetupTab::Hotkeys => {
+                             ui.heading("Hotkeys");
+                             ui.label("Used in the desk clock and windowed mode, and full-screen when keys don't exit. Combine keys with +, e.g. Ctrl+H. Leave empty to unbind.");
+                             ui.add_space(10.0);
+
+                             let mut changed = false;
+                             locked_ui(ui, &system, &["hotkeys"], |ui| {
+                                 egui::Grid::new("hotkeys").num_columns(2).spacing([20.0, 6.0]).show(ui, |ui| {
+                                     for action in config::HotkeyAction::ALL {
+                                         ui.label(action.label());
+                                         let keys = config.hotkeys.entry(action).or_default();
+                                         ui.horizontal(|ui| {
+                                             changed |= ui.add(egui::TextEdit::singleline(keys).desired_width(140.0)).changed();
+                                             if !keys.trim().is_empty() {
+                                                 if let Err(e) = keys.parse::<input::KeyChord>() {
+                                                     ui.label(egui::RichText::new(e).color(egui::Color32::LIGHT_RED));
+                                                 }
+                                             }
+                                         });
+                                         ui.end_row();
+                                     }
+                                 });
+                             });
+                             if changed {
+                                 saver.changed();
+                             }
+                         }
+                         SetupTab::Profiles => {
+                             let now = time.now_local().naive_local();
+                             if locked_ui(ui, &system, &["profiles", "profile_rules"], |ui| profiles_ui(ui, &mut config, &mut editing, &mut profile_name, &mut profile_error, &now, &system)) {
+                                 saver.changed();
                              }
                          }
                      }
@@ -723,10 +1503,23 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
 
         egui_macroquad::draw();
 
-        if is_key_pressed(KeyCode::Escape) {
+        if dismiss_reset {
+            config_reset = None;
+        }
+        if is_key_pressed(KeyCode::Escape) || is_quit_requested() {
             exit_setup = true;
         }
 
+        if let Some(edit) = &mut editing {
+            edit.sync(&config);
+        }
+        let saved = editing.as_ref().map_or(&config, |edit| &edit.base);
+        // Whatever runs next reads the file, so it has to be up to date
+        if exit_setup || next_mode.is_some() {
+            saver.flush(saved);
+        } else {
+            saver.poll(saved);
+        }
         if exit_setup {
             return None;
         }
@@ -738,106 +1531,623 @@ async fn run_setup(font: Option<&Font>) -> Option<AppMode> {
     }
 }
 
-async fn run_clock(_preview: bool, font: Option<&Font>) -> bool {
-    show_mouse(false);
-
-    #[cfg(windows)]
-    { windows_utils::make_window_cover_virtual_screen(); }
-    #[cfg(not(windows))]
-    { windows_utils::make_window_cover_virtual_screen(); }
+async fn run_clock(mode: &AppMode, font: Option<&Font>, time: Rc<dyn TimeSource>, overrides: &ViewOverrides) -> bool {
+    match mode {
+        AppMode::Clock { .. } => {
+            show_mouse(false);
+            windows_utils::make_window_cover_virtual_screen();
+        }
+        AppMode::Embedded { parent } => windows_utils::embed_in_preview(*parent),
+        AppMode::Hack { .. } => show_mouse(false),
+        _ => {}
+    }
+    let host_rect = match mode {
+        AppMode::Hack { window } => windows_utils::embed_in_hack_window(*window),
+        _ => None,
+    };
 
-    let config = load_config();
     let monitors = windows_utils::get_monitors();
     let virtual_rect = windows_utils::get_virtual_screen_rect();
+    // `ctl view` adds to these, so its views outlast reloads
+    let mut overrides = overrides.clone();
+    let mut config = AppConfig::default();
+    // Only setup moves a broken file aside, since only setup can say why
+    let loaded = load_config().unwrap_or_else(|e| {
+        eprintln!("Warning: Using the default config. {}", e);
+        AppConfig::default()
+    });
+    use_loaded_config(&mut config, loaded, mode, &monitors, &overrides);
+    let mut config_watcher = ConfigWatcher::new();
+
+    if *mode == AppMode::Desk {
+        // Closing the window saves where it was
+        prevent_quit();
+        let geometry = config.desk_geometry.filter(|g| on_screen(g, &monitors));
+        windows_utils::set_desk_window(geometry, config.desk_borderless, config.desk_always_on_top);
+    }
 
-    let mut clock_state = ClockState::new();
-    let mut departure_state = DepartureBoardState::new();
-
-    let mut mouse_init_pos = mouse_position();
-    let start_time = get_time();
-
-    // Prepare Pixelation Target (reused)
-    // We assume a standard size for simplification, or recreate if needed.
-    // For simplicity, we just use a target that covers the max monitor size or similar.
-    // Actually, pixelation should be per-monitor if sizes differ, but let's try one target per monitor if needed.
-    // Or just create on fly (expensive?).
-    // Let's create a map of render targets if we want pixelation.
-    // Given the constraints, let's just make one large target or create new ones if needed?
-    // Creating render targets in loop is bad.
-    // Let's pre-create one reasonably sized target and resize? Macroquad render targets are fixed size.
-    // We will skip pixelated mode optimization for multi-monitor for now or implement properly later.
-    // Actually, let's just create one target that matches virtual screen? No, texture limit.
-    // Let's just create targets for each monitor if pixelated is on.
+    // Full-screen, the window spans every monitor and each gets its own slice.
+    // Otherwise the window shows a single view: an xscreensaver window shows what its
+    // monitor is set to.
+    let spanning = matches!(mode, AppMode::Clock { .. } | AppMode::Hack { window: None });
+    let mut surfaces: Vec<MonitorSurface> = if spanning {
+        monitors.iter()
+            .map(|m| MonitorSurface::new(Some(m.name.clone()), m.width as f32, m.height as f32, time.clone(), &config.for_monitor(Some(&m.name))))
+            .collect()
+    } else {
+        let host = host_rect.and_then(|r| monitor_at(&monitors, r.center()));
+        let name = host.map(|m| m.name.clone());
+        let surface_config = config.for_monitor(name.as_deref());
+        vec![MonitorSurface::new(name.clone(), screen_width(), screen_height(), time.clone(), &surface_config)]
+    };
+    let control = ControlServer::start();
 
-    // For now, if pixelated, we just don't support it well on multi-monitor in this pass without more complexity.
-    // I'll implement standard rendering first. Pixelated will apply to the view logic.
+    let mut exit_watcher = ExitWatcher::new(&config.exit_policy, get_time());
+    let mut hotkeys = Hotkeys::new(&config.hotkeys);
+    let mut system = SystemConfig::load().unwrap_or_default();
+    // What's drawn: the config with the scheduled profile laid over it
+    let mut shown = config.clone();
+    let mut shown_profile: Option<String> = None;
+    let mut config_changed = false;
 
     loop {
-        if get_last_key_pressed().is_some() {
-            #[cfg(windows)]
-            { windows_utils::restore_window(); }
-            show_mouse(true);
+        let input = InputFrame::capture();
+        let window = Rect::new(0.0, 0.0, screen_width(), screen_height());
+        let rects = monitor_rects(&monitors, virtual_rect, window);
+
+        match mode {
+            AppMode::Clock { preview } => {
+                // Trying it out from setup must never lock anyone in
+                let leave_preview = *preview && exit_watcher.pin_prompt().is_none() && input.pressed.contains(&KeyCode::Escape);
+                if exit_watcher.update(&input) || leave_preview {
+                    windows_utils::restore_window();
+                    show_mouse(true);
+                    return false;
+                }
+            }
+            AppMode::Embedded { parent } => {
+                if !windows_utils::window_exists(*parent) {
+                    return false;
+                }
+            }
+            AppMode::Windowed => {
+                if input.pressed.contains(&KeyCode::Escape) {
+                    return false;
+                }
+            }
+            AppMode::Desk => {
+                if input.pressed.contains(&KeyCode::Escape) || is_quit_requested() {
+                    if let Some(geometry) = windows_utils::window_geometry() {
+                        save_change(|c| c.desk_geometry = Some(geometry));
+                    }
+                    return false;
+                }
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    apply_hotkey(HotkeyAction::CycleView, &mut config, true, None);
+                    config_changed = true;
+                }
+            }
+            AppMode::Hack { .. } | AppMode::Setup => {}
+        }
+        // Setup turned off quitting on close, so honor it here
+        if is_quit_requested() {
             return false;
         }
 
-        let now = get_time();
-        if now - start_time < 0.5 {
-            mouse_init_pos = mouse_position();
-        } else {
-            let current_pos = mouse_position();
-            if (current_pos.0 - mouse_init_pos.0).abs() > 10.0 || (current_pos.1 - mouse_init_pos.1).abs() > 10.0 {
-                #[cfg(windows)]
-                { windows_utils::restore_window(); }
-                show_mouse(true);
-                return false;
+        // Keys drive hotkeys wherever they don't close the clock
+        let hotkeys_active = match mode {
+            AppMode::Desk | AppMode::Windowed => true,
+            AppMode::Clock { .. } => {
+                let policy = &config.exit_policy;
+                !policy.kiosk && !policy.on_key && exit_watcher.pin_prompt().is_none()
+            }
+            _ => false,
+        };
+        if hotkeys_active {
+            for action in hotkeys.pressed(&input) {
+                let mouse = vec2(input.mouse.0, input.mouse.1);
+                let monitor = match rects.iter().position(|r| r.contains(mouse)) {
+                    Some(i) if spanning => Some(monitors[i].name.clone()),
+                    _ => surfaces[0].monitor.clone().or_else(|| shown_monitor(&config, &monitors).map(|m| m.name.clone())),
+                };
+                apply_hotkey(action, &mut config, *mode == AppMode::Desk, monitor.as_deref());
+                config_changed = true;
             }
         }
 
-        // Update States
-        clock_state.update(config.use_12h_format);
-        departure_state.update();
+        // Commands from `ctl`
+        let mut reloaded = false;
+        while let Some(request) = control.as_ref().and_then(ControlServer::poll) {
+            let result = apply_control(&request.command, &mut config, &mut surfaces, &monitors, mode, &mut overrides);
+            reloaded |= request.command == ControlCommand::Reload && result.is_ok();
+            config_changed = true;
+            request.respond(result);
+        }
 
-        // Draw background globally
-        let bg_color = mq_color_from_config(config.bg_color);
-        clear_background(bg_color);
+        // Edits to the config file; a bad edit keeps what's running
+        match config_watcher.as_mut().and_then(ConfigWatcher::poll) {
+            Some(Ok(loaded)) => {
+                use_loaded_config(&mut config, loaded, mode, &monitors, &overrides);
+                reloaded = true;
+                config_changed = true;
+            }
+            Some(Err(e)) => eprintln!("Warning: Ignoring the changed config. {}", e),
+            None => {}
+        }
+        if reloaded {
+            hotkeys = Hotkeys::new(&config.hotkeys);
+            // A new policy starts its own grace period; an unchanged one keeps any PIN being typed
+            if *exit_watcher.policy() != config.exit_policy {
+                exit_watcher = ExitWatcher::new(&config.exit_policy, get_time());
+            }
+            system = SystemConfig::load().unwrap_or_default();
+            if *mode == AppMode::Desk {
+                windows_utils::set_desk_window(None, config.desk_borderless, config.desk_always_on_top);
+            }
+        }
 
-        if config.pixelated {
-             let mut camera = Camera2D {
-                render_target: Some(render_target.clone()),
-                ..Default::default()
-            };
-            camera.zoom = vec2(2.0 / pixel_width as f32, 2.0 / pixel_height as f32);
-            camera.target = vec2(pixel_width as f32 / 2.0, pixel_height as f32 / 2.0);
+        let profile = profiles::scheduled(&config, &time.now_local().naive_local()).map(str::to_string);
+        if config_changed || profile != shown_profile {
+            shown = profiles::apply(&config, profile.as_deref()).into_owned();
+            if profile.is_some() {
+                // Profiles saved before a setting was locked may still hold it
+                system.enforce(&mut shown);
+                // This run's overrides win over the profile's views
+                fill_monitor_views(&mut shown, &monitors);
+                overrides.apply(&mut shown, &monitors);
+            }
+            shown_profile = profile;
+            config_changed = false;
+        }
 
-            set_camera(&camera);
-            clear_background(bg_color);
-            
-            // Draw clock into small texture
-            let small_rect = Rect::new(0.0, 0.0, pixel_width as f32, pixel_height as f32);
-            draw_clock_face(&config, &mut time_state, small_rect, font, false);
-            
-            set_default_camera();
+        // Gaps between monitors of different sizes stay black
+        clear_background(BLACK);
 
-            // Draw texture to screen scaled up
-            draw_texture_ex(
-                &render_target.texture,
-                clock_rect.x,
-                clock_rect.y,
-                WHITE,
-                DrawTextureParams {
-                    dest_size: Some(vec2(clock_rect.w, clock_rect.h)),
-                    flip_y: true, // Render targets are flipped
-                    ..Default::default()
+        for surface in &mut surfaces {
+            let view = match &surface.monitor {
+                Some(name) => shown.monitor_views.get(name).cloned().unwrap_or_default(),
+                None if *mode == AppMode::Desk => shown.desk_view.clone(),
+                None => single_view(&shown, &monitors),
+            };
+            let rect = match &surface.monitor {
+                Some(name) if spanning => {
+                    let Some(i) = monitors.iter().position(|m| m.name == *name) else { continue };
+                    rects[i]
                 }
-            );
-        } else {
-            draw_clock_face(&config, &mut time_state, clock_rect, font, false);
+                _ => window,
+            };
+            // Each monitor's own overrides, e.g. a larger scale on a small panel
+            let surface_config = shown.for_monitor(surface.monitor.as_deref());
+            surface.views.update(&surface_config);
+            surface.draw(&view, &surface_config, rect, font);
+        }
+        if let Some((typed, wrong)) = exit_watcher.pin_prompt() {
+            draw_pin_prompt(typed, wrong, font);
         }
 
         next_frame().await;
     }
 }
 
+/// The Profiles tab: which profile setup edits, and the rules that schedule them.
+fn profiles_ui(
+    ui: &mut egui::Ui,
+    config: &mut AppConfig,
+    editing: &mut Option<ProfileEdit>,
+    name: &mut String,
+    error: &mut Option<String>,
+    now: &chrono::NaiveDateTime,
+    system: &SystemConfig,
+) -> bool {
+    let mut changed = false;
+    ui.heading("Profiles");
+    ui.label("A profile changes some settings while a rule below schedules it. Pick one to edit it in the other tabs.");
+    ui.add_space(10.0);
+
+    let current = editing.as_ref().map(|edit| edit.name.clone());
+    let names: Vec<String> = profiles::saved(config, editing).profiles.iter().map(|p| p.name.clone()).collect();
+    let mut selected = current.clone();
+    ui.horizontal(|ui| {
+        ui.label("Editing");
+        egui::ComboBox::from_id_salt("editing_profile")
+            .selected_text(selected.as_deref().unwrap_or("Default"))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut selected, None, "Default");
+                for profile in &names {
+                    ui.selectable_value(&mut selected, Some(profile.clone()), profile);
+                }
+            });
+    });
+    if selected != current {
+        profiles::select(config, editing, selected.as_deref(), system);
+        *name = selected.unwrap_or_default();
+        *error = None;
+    }
+
+    ui.horizontal(|ui| {
+        if ui.button("New").clicked() {
+            profiles::select(config, editing, None, system);
+            let created = profiles::create(config);
+            profiles::select(config, editing, Some(&created), system);
+            *name = created;
+            changed = true;
+        }
+        if ui.button("Duplicate").on_hover_text("Copies the profile being edited; Default copies every setting.").clicked() {
+            let from = editing.as_ref().map(|edit| edit.name.clone());
+            profiles::select(config, editing, None, system);
+            let copy = profiles::duplicate(config, from.as_deref(), system);
+            profiles::select(config, editing, Some(&copy), system);
+            *name = copy;
+            changed = true;
+        }
+        ui.add_enabled_ui(editing.is_some(), |ui| {
+            ui.add(egui::TextEdit::singleline(name).desired_width(140.0));
+            if ui.button("Rename").clicked() {
+                if let Some(from) = editing.as_ref().map(|edit| edit.name.clone()) {
+                    profiles::select(config, editing, None, system);
+                    *error = profiles::rename(config, &from, name).err();
+                    let to = if error.is_some() { from } else { name.trim().to_string() };
+                    profiles::select(config, editing, Some(&to), system);
+                    *name = to;
+                    changed = true;
+                }
+            }
+            if ui.button("Delete").clicked() {
+                if let Some(edit) = editing.take() {
+                    let deleted = edit.name.clone();
+                    edit.finish(config);
+                    profiles::delete(config, &deleted);
+                    name.clear();
+                    changed = true;
+                }
+            }
+        });
+    });
+    if let Some(e) = error {
+        ui.label(egui::RichText::new(e.as_str()).color(egui::Color32::LIGHT_RED));
+    }
+
+    ui.add_space(20.0);
+    ui.separator();
+    ui.add_space(20.0);
+
+    ui.heading("Schedule");
+    ui.label("The first rule that matches picks the profile; otherwise Default is shown. End before start runs past midnight.");
+    ui.add_space(10.0);
+
+    let saved = profiles::saved(config, editing);
+    let mut remove: Option<usize> = None;
+    for (i, rule) in saved.profile_rules.iter_mut().enumerate() {
+        ui.group(|ui| {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_salt(("rule_profile", i))
+                    .selected_text(rule.profile.as_str())
+                    .show_ui(ui, |ui| {
+                        for profile in &names {
+                            changed |= ui.selectable_value(&mut rule.profile, profile.clone(), profile).changed();
+                        }
+                    });
+                for day in Day::ALL {
+                    let on = rule.days.contains(&day);
+                    if ui.selectable_label(on, format!("{:?}", day)).clicked() {
+                        if on {
+                            rule.days.retain(|d| *d != day);
+                        } else {
+                            rule.days.push(day);
+                            rule.days.sort_by_key(|d| *d as usize);
+                        }
+                        changed = true;
+                    }
+                }
+                changed |= ui.add(egui::TextEdit::singleline(&mut rule.start).desired_width(50.0)).changed();
+                ui.label("to");
+                changed |= ui.add(egui::TextEdit::singleline(&mut rule.end).desired_width(50.0)).changed();
+                if ui.button("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+            if let Err(e) = rule.times() {
+                ui.label(egui::RichText::new(e).color(egui::Color32::LIGHT_RED));
+            } else if rule.days.is_empty() {
+                ui.label("Every day");
+            }
+        });
+    }
+    if let Some(i) = remove {
+        saved.profile_rules.remove(i);
+        changed = true;
+    }
+
+    ui.add_space(10.0);
+    if ui.add_enabled(!names.is_empty(), egui::Button::new("Add Rule")).clicked() {
+        saved.profile_rules.push(ProfileRule {
+            profile: current.unwrap_or_else(|| names[0].clone()),
+            days: vec![Day::Mon, Day::Tue, Day::Wed, Day::Thu, Day::Fri],
+            start: "09:00".to_string(),
+            end: "18:00".to_string(),
+        });
+        changed = true;
+    }
+    ui.add_space(10.0);
+    ui.label(format!("Scheduled now: {}", profiles::scheduled(saved, now).unwrap_or("Default")));
+    changed
+}
+
+/// Shows the widgets read-only while the system config locks any of `keys`.
+fn locked_ui<R>(ui: &mut egui::Ui, system: &SystemConfig, keys: &[&str], add_contents: impl FnOnce(&mut egui::Ui) -> R) -> R {
+    let locked = keys.iter().any(|key| system.is_locked(key));
+    let shown = ui.add_enabled_ui(!locked, add_contents);
+    if locked {
+        shown.response.on_hover_text("Locked by the system config");
+    }
+    shown.inner
+}
+
+/// Edits one monitor's overrides. Ticking a setting starts it from the global value.
+fn monitor_settings_ui(ui: &mut egui::Ui, settings: &mut MonitorSettings, global: &AppConfig) -> bool {
+    fn setting<T: Clone>(
+        ui: &mut egui::Ui,
+        label: &str,
+        value: &mut Option<T>,
+        global: &T,
+        edit: impl FnOnce(&mut egui::Ui, &mut T) -> bool,
+    ) -> bool {
+        ui.horizontal(|ui| {
+            let mut on = value.is_some();
+            let mut changed = ui.checkbox(&mut on, label).changed();
+            if changed {
+                *value = on.then(|| global.clone());
+            }
+            if let Some(value) = value {
+                changed |= edit(ui, value);
+            }
+            changed
+        })
+        .inner
+    }
+    let on_off = |ui: &mut egui::Ui, value: &mut bool| ui.checkbox(value, "On").changed();
+    let color = |ui: &mut egui::Ui, value: &mut [f32; 3]| ui.color_edit_button_rgb(value).changed();
+
+    let mut changed = false;
+    changed |= setting(ui, "Scale (%)", &mut settings.scale, &global.scale, |ui, v| {
+        let mut pct = *v * 100.0;
+        let changed = ui.add(egui::Slider::new(&mut pct, 20.0..=100.0)).changed();
+        *v = pct / 100.0;
+        changed
+    });
+    changed |= setting(ui, "Background", &mut settings.bg_color, &global.bg_color, color);
+    changed |= setting(ui, "Card Background", &mut settings.card_color, &global.card_color, color);
+    changed |= setting(ui, "Text / Digits", &mut settings.text_color, &global.text_color, color);
+    changed |= setting(ui, "12-Hour Format", &mut settings.use_12h_format, &global.use_12h_format, on_off);
+    changed |= setting(ui, "Show Seconds", &mut settings.show_seconds, &global.show_seconds, on_off);
+    changed |= setting(ui, "Retro Pixelated Mode", &mut settings.pixelated, &global.pixelated, on_off);
+    changed
+}
+
+/// Applies a hotkey to the running config and saves the same change.
+///
+/// `CycleView` switches the desk clock's own view when `desk` is set, otherwise `monitor`'s.
+fn apply_hotkey(action: HotkeyAction, config: &mut AppConfig, desk: bool, monitor: Option<&str>) {
+    match action {
+        HotkeyAction::Toggle12h => {
+            let value = !config.use_12h_format;
+            change_and_save(config, |c| c.use_12h_format = value);
+        }
+        HotkeyAction::ToggleSeconds => {
+            let value = !config.show_seconds;
+            change_and_save(config, |c| c.show_seconds = value);
+        }
+        HotkeyAction::CycleTheme => {
+            let mut next = config.clone();
+            next.cycle_theme();
+            let (bg, card, text) = (next.bg_color, next.card_color, next.text_color);
+            change_and_save(config, |c| {
+                c.bg_color = bg;
+                c.card_color = card;
+                c.text_color = text;
+            });
+        }
+        HotkeyAction::CycleView if desk => {
+            let view = config.desk_view.next();
+            change_and_save(config, |c| c.desk_view = view.clone());
+        }
+        HotkeyAction::CycleView => {
+            let Some(name) = monitor else { return };
+            let view = config.monitor_views.get(name).cloned().unwrap_or_default().next();
+            change_and_save(config, |c| { c.monitor_views.insert(name.to_string(), view.clone()); });
+        }
+        HotkeyAction::ScaleUp | HotkeyAction::ScaleDown => {
+            let step = if action == HotkeyAction::ScaleUp { 0.05 } else { -0.05 };
+            let value = (config.scale + step).clamp(0.2, 1.0);
+            change_and_save(config, |c| c.scale = value);
+        }
+        HotkeyAction::ToggleBorderless | HotkeyAction::ToggleAlwaysOnTop if desk => {
+            let borderless = config.desk_borderless ^ (action == HotkeyAction::ToggleBorderless);
+            let on_top = config.desk_always_on_top ^ (action == HotkeyAction::ToggleAlwaysOnTop);
+            windows_utils::set_desk_window(None, borderless, on_top);
+            change_and_save(config, |c| {
+                c.desk_borderless = borderless;
+                c.desk_always_on_top = on_top;
+            });
+        }
+        HotkeyAction::ToggleBorderless | HotkeyAction::ToggleAlwaysOnTop => {}
+    }
+}
+
+/// Makes a change to the running config and saves the same change.
+fn change_and_save(config: &mut AppConfig, change: impl Fn(&mut AppConfig)) {
+    change(config);
+    // Saving leaves locked settings alone, so the running clock does too
+    if let Ok(system) = SystemConfig::load() {
+        system.enforce(config);
+    }
+    save_change(change);
+}
+
+/// Saves a change made while the clock runs, without saving this run's overrides along with it.
+fn save_change(change: impl FnOnce(&mut AppConfig)) {
+    match load_config() {
+        Ok(mut saved) => {
+            change(&mut saved);
+            if let Err(e) = save_config(&saved) {
+                eprintln!("Warning: {}", e);
+            }
+        }
+        // Never replace a file we couldn't read
+        Err(e) => eprintln!("Warning: Not saving the change. {}", e),
+    }
+}
+
+/// Replaces the running config with one read from disk, keeping this run's overrides.
+fn use_loaded_config(
+    config: &mut AppConfig,
+    loaded: AppConfig,
+    mode: &AppMode,
+    monitors: &[windows_utils::MonitorInfo],
+    overrides: &ViewOverrides,
+) {
+    *config = loaded;
+    if identify_monitors(config, monitors) {
+        // Later saves start from the file, so it has to know where entries went
+        save_change(|c| { identify_monitors(c, monitors); });
+    }
+    fill_monitor_views(config, monitors);
+    overrides.apply(config, monitors);
+    if *mode == AppMode::Desk {
+        if let Some(view) = &overrides.view {
+            config.desk_view = view.clone();
+        }
+    }
+}
+
+/// Whether a remembered window position still overlaps a connected monitor.
+fn on_screen(geometry: &WindowGeometry, monitors: &[windows_utils::MonitorInfo]) -> bool {
+    let window = Rect::new(geometry.x as f32, geometry.y as f32, geometry.width as f32, geometry.height as f32);
+    geometry.width > 0 && geometry.height > 0 && monitors.iter().any(|m| {
+        window.overlaps(&Rect::new(m.x as f32, m.y as f32, m.width as f32, m.height as f32))
+    })
+}
+
+/// One monitor's slice of the clock window, with its own animation state.
+struct MonitorSurface {
+    /// `None` when the window shows a single view rather than spanning monitors.
+    monitor: Option<String>,
+    views: ViewStates,
+    pixel_target: RenderTarget,
+}
+
+impl MonitorSurface {
+    fn new(monitor: Option<String>, width: f32, height: f32, time: Rc<dyn TimeSource>, config: &AppConfig) -> Self {
+        // Pixelated mode draws at 1/8 of the monitor's resolution and scales up
+        let pixel_target = render_target((width / 8.0).max(1.0) as u32, (height / 8.0).max(1.0) as u32);
+        pixel_target.texture.set_filter(FilterMode::Nearest);
+        Self { monitor, views: ViewStates::new(time, config), pixel_target }
+    }
+
+    fn draw(&mut self, view: &ViewType, config: &AppConfig, rect: Rect, font: Option<&Font>) {
+        if *view == ViewType::Off {
+            self.views.draw(view, config, rect, font, false);
+            return;
+        }
+
+        let bg_color = mq_color_from_config(config.bg_color);
+        if !config.pixelated {
+            draw_rectangle(rect.x, rect.y, rect.w, rect.h, bg_color);
+            self.views.draw(view, config, rect, font, false);
+            return;
+        }
+
+        let texture = &self.pixel_target.texture;
+        let (pixel_w, pixel_h) = (texture.width(), texture.height());
+        let mut camera = Camera2D {
+            render_target: Some(self.pixel_target.clone()),
+            ..Default::default()
+        };
+        camera.zoom = vec2(2.0 / pixel_w, 2.0 / pixel_h);
+        camera.target = vec2(pixel_w / 2.0, pixel_h / 2.0);
+
+        set_camera(&camera);
+        clear_background(bg_color);
+        self.views.draw(view, config, Rect::new(0.0, 0.0, pixel_w, pixel_h), font, false);
+        set_default_camera();
+
+        draw_texture_ex(
+            texture,
+            rect.x,
+            rect.y,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(rect.w, rect.h)),
+                flip_y: true, // Render targets are flipped
+                ..Default::default()
+            }
+        );
+    }
+}
+
+fn monitor_at(monitors: &[windows_utils::MonitorInfo], point: Vec2) -> Option<&windows_utils::MonitorInfo> {
+    monitors.iter().find(|m| {
+        Rect::new(m.x as f32, m.y as f32, m.width as f32, m.height as f32).contains(point)
+    })
+}
+
+/// Where each monitor lands in the window, given the window covers `virtual_rect`.
+///
+/// Scaling keeps this right when the window's pixels don't match desktop pixels (high DPI).
+fn monitor_rects(monitors: &[windows_utils::MonitorInfo], virtual_rect: Rect, window: Rect) -> Vec<Rect> {
+    let sx = window.w / virtual_rect.w.max(1.0);
+    let sy = window.h / virtual_rect.h.max(1.0);
+    monitors.iter()
+        .map(|m| Rect::new(
+            window.x + (m.x as f32 - virtual_rect.x) * sx,
+            window.y + (m.y as f32 - virtual_rect.y) * sy,
+            m.width as f32 * sx,
+            m.height as f32 * sy,
+        ))
+        .collect()
+}
+
+fn apply_control(
+    command: &ControlCommand,
+    config: &mut AppConfig,
+    surfaces: &mut [MonitorSurface],
+    monitors: &[windows_utils::MonitorInfo],
+    mode: &AppMode,
+    overrides: &mut ViewOverrides,
+) -> Result<String, String> {
+    match command {
+        ControlCommand::SetView { monitor, view } => {
+            if *mode == AppMode::Desk {
+                return Err("The desk clock has no monitor views; use its Next View hotkey instead".to_string());
+            }
+            if !monitors.iter().any(|m| m.name == *monitor) {
+                let names: Vec<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+                return Err(format!("Unknown monitor '{}' (available: {})", monitor, names.join(", ")));
+            }
+            overrides.monitor_views.insert(monitor.clone(), view.clone());
+            config.monitor_views.insert(monitor.clone(), view.clone());
+            Ok(format!("{} now shows {:?}", monitor, view))
+        }
+        ControlCommand::Reload => {
+            let loaded = load_config().map_err(|e| e.to_string())?;
+            use_loaded_config(config, loaded, mode, monitors, overrides);
+            Ok("Config reloaded".to_string())
+        }
+        ControlCommand::PushMessage(_) => {
+            surfaces.iter_mut().try_for_each(|s| s.views.apply(command))?;
+            Ok("Message queued".to_string())
+        }
+        ControlCommand::StartTimer(duration) => {
+            surfaces.iter_mut().try_for_each(|s| s.views.apply(command))?;
+            Ok(format!("Timer started for {}s", duration.as_secs()))
+        }
+        ControlCommand::StopTimer => {
+            surfaces.iter_mut().try_for_each(|s| s.views.apply(command))?;
+            Ok("Timer stopped".to_string())
+        }
+    }
+}
+
 // -- Helpers --
 
 fn mq_color_from_config(c: [f32; 3]) -> Color {
@@ -850,7 +2160,6 @@ fn draw_clock_face(
     rect: Rect, // Draw area
     font: Option<&Font>,
     is_preview: bool,
-    flip_content: bool,
 ) {
     let sw = rect.w;
     let sh = rect.h;
@@ -882,18 +2191,7 @@ fn draw_clock_face(
     let corner_radius = config.corner_radius * (if is_preview { 0.5 } else { 1.0 });
 
     // Animation progress
-    let mut progress = 0.0;
-    if let Some(start) = state.animation_start {
-        let elapsed = (get_time() - start) * 1000.0;
-        let duration = config.animation_speed as f64;
-        progress = (elapsed / duration) as f32;
-        if progress >= 1.0 {
-            progress = 1.0;
-            state.animation_start = None;
-            state.previous_digits = state.current_digits.clone();
-            state.previous_seconds = state.current_seconds.clone();
-        }
-    }
+    let progress = state.progress(config.animation_speed);
 
     let mut x = start_x;
 
@@ -905,7 +2203,7 @@ fn draw_clock_face(
         let prev_digit = &state.previous_digits[i];
         let p = if digit == prev_digit { 1.0 } else { progress };
 
-        draw_single_flip_card(x, start_y, card_width, card_height, digit, prev_digit, p, font, font_size, card_color, text_color, corner_radius, flip_content);
+        draw_single_flip_card(x, start_y, card_width, card_height, digit, prev_digit, p, font, font_size, card_color, text_color, corner_radius);
 
         x += card_width + spacing;
         if i == 1 {
@@ -922,7 +2220,7 @@ fn draw_clock_face(
             let prev_digit = &state.previous_seconds[i];
             let p = if digit == prev_digit { 1.0 } else { progress };
 
-            draw_single_flip_card(x, start_y, card_width, card_height, digit, prev_digit, p, font, font_size, card_color, text_color, corner_radius, flip_content);
+            draw_single_flip_card(x, start_y, card_width, card_height, digit, prev_digit, p, font, font_size, card_color, text_color, corner_radius);
 
             x += card_width + spacing;
         }
@@ -956,88 +2254,129 @@ fn draw_departure_board(
     let mut y = rect.y + (rect.h - (num_rows * row_height)) / 2.0;
 
     for (i, row) in rows.iter().enumerate() {
-        let city_name = CITIES[i].name;
-
         let mut x = rect.x + margin;
 
-        // 1. Draw City Name (Static Text, simulated flip cards or just cards)
-        // We can just draw them as static cards
-        for c in city_name.chars() {
-            let s = c.to_string();
-            draw_single_flip_card(x, y, card_width, card_height, &s, &s, 1.0, font, font_size, card_color, text_color, corner_radius);
+        // Time since the row changed; each flap works out its own roll from it
+        let elapsed = match row.anim_start {
+            Some(start) => (state.time.seconds() - start) * 1000.0,
+            None => f64::INFINITY,
+        };
+        let draw_flap = |x: f32, from: char, to: char, column: usize| {
+            let flap = split_flap::flap_at(config, from, to, elapsed, i, column);
+            draw_single_flip_card(x, y, card_width, card_height, &flap.next.to_string(), &flap.current.to_string(), flap.progress, font, font_size, card_color, text_color, corner_radius);
+        };
+
+        // 1. Draw City Name (only flips when the name or zone abbreviation changes)
+        let label_len = row.label_width();
+        for j in 0..label_len {
+            let c = row.label.chars().nth(j).unwrap_or(' ');
+            let prev_c = row.prev_label.chars().nth(j).unwrap_or(' ');
+            draw_flap(x, prev_c, c, j);
             x += card_width + spacing;
         }
+        let mut flap_column = label_len;
 
-        // Align Right for Time
-        // Layout: [Day] [AM/PM] [Time]
-        // But the image shows: Time ... AM/PM ... Day
-        // Let's stick to image: Time (Right aligned relative to center?), AM/PM, Day
-
-        // Let's position from right side
+        // Columns are laid out left to right, right-aligned against the edge
         let right_edge = rect.x + rect.w - margin;
+        let column_gap = card_width * 1.5;
+        let widths: Vec<usize> = state.columns.iter()
+            .map(|c| departure_board::column_width(*c, state.show_seconds))
+            .collect();
+        let total_width: f32 = widths.iter()
+            .map(|n| *n as f32 * (card_width + spacing) - spacing)
+            .sum::<f32>() + column_gap * widths.len().saturating_sub(1) as f32;
+        let mut cur_x = right_edge - total_width;
+
+        for (col, width) in widths.iter().enumerate() {
+            let cell = row.cells.get(col).map(String::as_str).unwrap_or("");
+            let prev_cell = row.prev_cells.get(col).map(String::as_str).unwrap_or("");
+
+            for j in 0..*width {
+                let c = cell.chars().nth(j).unwrap_or(' ');
+                let prev_c = prev_cell.chars().nth(j).unwrap_or(' ');
+                let cx = cur_x + (j as f32 * (card_width + spacing));
+
+                if c == ':' && prev_c == ':' {
+                    // Draw just colon, static
+                    draw_text_centered(cx, y, card_width, card_height, ":", font, font_size, text_color);
+                } else {
+                    draw_flap(cx, prev_c, c, flap_column + j);
+                }
+            }
 
-        // Day
-        let day_width = (3.0 * card_width) + (2.0 * spacing);
-        let mut cur_x = right_edge - day_width;
-
-        // Calc animation progress
-        let progress = if let Some(start) = row.anim_start {
-            let elapsed = (get_time() - start) * 1000.0;
-            let duration = config.animation_speed as f64;
-            let p = (elapsed / duration) as f32;
-            if p > 1.0 { 1.0 } else { p }
-        } else {
-            1.0
-        };
-
-        // Draw Day
-        // row.day is "WED"
-        for (j, c) in row.day.chars().enumerate() {
-            let s = c.to_string();
-            let prev_c = row.prev_day.chars().nth(j).unwrap_or(' ').to_string();
-            let p = if s == prev_c { 1.0 } else { progress };
-
-            draw_single_flip_card(cur_x + (j as f32 * (card_width + spacing)), y, card_width, card_height, &s, &prev_c, p, font, font_size, card_color, text_color, corner_radius);
+            flap_column += *width;
+            cur_x += *width as f32 * (card_width + spacing) - spacing + column_gap;
         }
 
-        // Gap
-        cur_x -= card_width * 1.5;
+        y += row_height;
+    }
 
-        // AM/PM
-        let ampm_width = (2.0 * card_width) + spacing;
-        cur_x -= ampm_width;
-         for (j, c) in row.ampm.chars().enumerate() {
-            let s = c.to_string();
-            let prev_c = row.prev_ampm.chars().nth(j).unwrap_or(' ').to_string();
-            let p = if s == prev_c { 1.0 } else { progress };
+}
 
-            draw_single_flip_card(cur_x + (j as f32 * (card_width + spacing)), y, card_width, card_height, &s, &prev_c, p, font, font_size, card_color, text_color, corner_radius);
-        }
+fn draw_message_board(
+    config: &AppConfig,
+    state: &mut MessageBoardState,
+    rect: Rect,
+    font: Option<&Font>
+) {
+    let rows = state.lines.len().max(1) as f32;
+    let columns = state.columns.max(1) as f32;
 
-        // Gap
-        cur_x -= card_width * 1.5;
+    // Layout: same card proportions as the departure board, sized so the whole grid fits
+    let margin = 20.0 * config.scale;
+    let available_w = rect.w - (margin * 2.0);
+    let available_h = rect.h - (margin * 2.0);
+    let width_limited = available_w / (columns * 1.1 - 0.1) / 0.6;
+    let height_limited = available_h / rows * 0.8;
+    let card_height = width_limited.min(height_limited) * config.scale;
+    let card_width = card_height * 0.6;
+    let spacing = card_width * 0.1;
+    let row_height = card_height / 0.8;
 
-        // Time (HH:MM) - 5 chars
-        let time_width = (5.0 * card_width) + (4.0 * spacing);
-        cur_x -= time_width;
+    let font_size = (card_height * 0.7) as u16;
+    let corner_radius = config.corner_radius * 0.5;
 
-        for (j, c) in row.time_str.chars().enumerate() {
-             let s = c.to_string();
-             let prev_c = row.prev_time_str.chars().nth(j).unwrap_or(' ').to_string();
+    let card_color = mq_color_from_config(config.card_color);
+    let text_color = mq_color_from_config(config.text_color);
 
-             let p = if s == prev_c { 1.0 } else { progress };
+    let grid_w = columns * (card_width + spacing) - spacing;
+    let start_x = rect.x + (rect.w - grid_w) / 2.0;
+    let mut y = rect.y + (rect.h - rows * row_height) / 2.0;
 
-             if c == ':' {
-                 // Draw just colon, static
-                  draw_text_centered(cur_x + (j as f32 * (card_width + spacing)), y, card_width, card_height, ":", font, font_size, text_color);
-             } else {
-                 draw_single_flip_card(cur_x + (j as f32 * (card_width + spacing)), y, card_width, card_height, &s, &prev_c, p, font, font_size, card_color, text_color, corner_radius);
-             }
-        }
+    let elapsed = match state.anim_start {
+        Some(start) => (state.time.seconds() - start) * 1000.0,
+        None => f64::INFINITY,
+    };
 
+    for (i, line) in state.lines.iter().enumerate() {
+        let prev_line = state.prev_lines.get(i).map(String::as_str).unwrap_or("");
+        for j in 0..state.columns {
+            let c = line.chars().nth(j).unwrap_or(' ');
+            let prev_c = prev_line.chars().nth(j).unwrap_or(' ');
+            let flap = split_flap::flap_at(config, prev_c, c, elapsed, i, j);
+            let x = start_x + j as f32 * (card_width + spacing);
+            draw_single_flip_card(x, y, card_width, card_height, &flap.next.to_string(), &flap.current.to_string(), flap.progress, font, font_size, card_color, text_color, corner_radius);
+        }
         y += row_height;
     }
+}
 
+/// Asks for the exit PIN in the middle of the window.
+fn draw_pin_prompt(typed: usize, wrong: bool, font: Option<&Font>) {
+    let (w, h) = (screen_width(), screen_height());
+    draw_rectangle(0.0, 0.0, w, h, Color::new(0.0, 0.0, 0.0, 0.6));
+
+    let box_w = (w * 0.4).clamp(240.0, 480.0);
+    let box_h = box_w * 0.4;
+    let (x, y) = ((w - box_w) / 2.0, (h - box_h) / 2.0);
+    draw_rounded_rectangle(x, y, box_w, box_h, 8.0, Color::new(0.1, 0.1, 0.1, 0.95));
+
+    let font_size = (box_h * 0.2) as u16;
+    let title = if wrong { "Wrong PIN, try again" } else { "Enter PIN to exit" };
+    draw_text_centered(x, y, box_w, box_h * 0.5, title, font, font_size, WHITE);
+    let dots = "*".repeat(typed.clamp(1, 12));
+    let dots_color = if typed == 0 { Color::new(1.0, 1.0, 1.0, 0.2) } else { WHITE };
+    draw_text_centered(x, y + box_h * 0.4, box_w, box_h * 0.5, &dots, font, font_size, dots_color);
 }
 
 fn draw_separator(cx: f32, y: f32, h: f32, color: Color) {
@@ -1048,6 +2387,7 @@ fn draw_separator(cx: f32, y: f32, h: f32, color: Color) {
     draw_circle(cx, cy + gap, dot_size, color);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn draw_single_flip_card(
     x: f32, y: f32, w: f32, h: f32,
     content: &str, prev_content: &str,
@@ -1057,7 +2397,6 @@ fn draw_single_flip_card(
     bg_color: Color,
     text_color: Color,
     radius: f32,
-    flip_content: bool,
 ) {
     // Draw Background
     if radius > 0.0 {
@@ -1066,8 +2405,8 @@ fn draw_single_flip_card(
         draw_rectangle(x, y, w, h, bg_color);
     }
 
-    let display_digit = if progress > 0.5 { digit } else { prev_digit };
-    draw_digit_centered(x, y, w, h, display_digit, font, font_size, text_color);
+    let display = if progress > 0.5 { content } else { prev_content };
+    draw_text_centered(x, y, w, h, display, font, font_size, text_color);
 
     // Split line
     let mid_y = y + h / 2.0;
@@ -1091,9 +2430,9 @@ fn draw_rounded_rectangle(x: f32, y: f32, w: f32, h: f32, r: f32, color: Color)
     draw_circle(x + w - r, y + h - r, r, color);
 }
 
-fn draw_digit_centered(x: f32, y: f32, w: f32, h: f32, digit: u32, font: Option<&Font>, font_size: u16, color: Color) {
-    let text = digit.to_string();
-    let dims = measure_text(&text, font, font_size, 1.0);
+#[allow(clippy::too_many_arguments)]
+fn draw_text_centered(x: f32, y: f32, w: f32, h: f32, text: &str, font: Option<&Font>, font_size: u16, color: Color) {
+    let dims = measure_text(text, font, font_size, 1.0);
     let tx = x + (w - dims.width) / 2.0;
     let ty = y + (h - dims.height) / 2.0 + dims.offset_y;
 
@@ -1101,7 +2440,206 @@ fn draw_digit_centered(x: f32, y: f32, w: f32, h: f32, digit: u32, font: Option<
         font,
         font_size,
         color,
-        font_scale_aspect: if flip_x { -1.0 } else { 1.0 },
         ..Default::default()
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration};
+    use time_source::FixedClock;
+
+    fn fixed(rfc3339: &str) -> Rc<FixedClock> {
+        Rc::new(FixedClock::new(DateTime::parse_from_rfc3339(rfc3339).unwrap()))
+    }
+
+    fn shown(state: &ClockState) -> String {
+        state.current_digits.concat() + &state.current_seconds.concat()
+    }
+
+    #[test]
+    fn test_clock_12h_rollover() {
+        let clock = fixed("2024-06-01T12:59:58.600+02:00");
+        let mut state = ClockState::new(clock.clone());
+        state.update(true);
+        assert_eq!(shown(&state), "125958");
+
+        for expected in ["125959", "010000", "010001"] {
+            clock.advance(Duration::milliseconds(400));
+            state.update(true);
+            assert_eq!(shown(&state), expected);
+            assert_eq!(state.progress(600), 0.0);
+            clock.advance(Duration::milliseconds(600));
+            assert_eq!(state.progress(600), 1.0);
+        }
+        assert_eq!(state.previous_digits.concat(), "0100");
+    }
+
+    #[test]
+    fn test_clock_midnight() {
+        let clock = fixed("2024-12-31T23:59:59-05:00");
+        let mut state = ClockState::new(clock.clone());
+        state.update(false);
+        assert_eq!(shown(&state), "235959");
+
+        clock.advance(Duration::seconds(1));
+        state.update(false);
+        assert_eq!(shown(&state), "000000");
+        assert_eq!(state.previous_digits.concat(), "2359");
+    }
+
+    #[test]
+    fn test_clock_countdown() {
+        let clock = fixed("2024-06-01T13:00:00.500Z");
+        let mut state = ClockState::new(clock.clone());
+        state.start_countdown(std::time::Duration::from_secs(90 * 60 + 2)).unwrap();
+        state.update(true);
+        assert_eq!(shown(&state), "013002");
+
+        clock.advance(Duration::milliseconds(90 * 60 * 1000 + 1500));
+        state.progress(600);
+        state.update(true);
+        assert_eq!(shown(&state), "000001");
+
+        clock.advance(Duration::seconds(10));
+        state.progress(600);
+        state.update(true);
+        assert_eq!(shown(&state), "000000");
+
+        state.stop_countdown();
+        clock.advance(Duration::milliseconds(600));
+        state.progress(600);
+        state.update(true);
+        assert_eq!(state.current_digits.concat(), "0230");
+    }
+
+    #[test]
+    fn test_clock_waits_for_running_flip() {
+        let clock = fixed("2024-06-01T08:00:00Z");
+        let mut state = ClockState::new(clock.clone());
+        clock.advance(Duration::milliseconds(1000));
+        state.update(false);
+        assert_eq!(shown(&state), "080001");
+
+        // A new second arrives mid-flip; the display catches up once the flip ends.
+        clock.advance(Duration::milliseconds(1000));
+        assert!(state.progress(1500) < 1.0);
+        state.update(false);
+        assert_eq!(shown(&state), "080001");
+        clock.advance(Duration::milliseconds(500));
+        assert_eq!(state.progress(1500), 1.0);
+        state.update(false);
+        assert_eq!(shown(&state), "080002");
+    }
+
+    fn monitor(name: &str, is_primary: bool) -> windows_utils::MonitorInfo {
+        windows_utils::MonitorInfo { name: name.to_string(), x: 0, y: 0, width: 1920, height: 1080, is_primary, edid: None }
+    }
+
+    #[test]
+    fn test_view_overrides() {
+        let monitors = vec![monitor("DP-1", true), monitor("HDMI-1", false)];
+        let mut config = AppConfig::default();
+        config.monitor_views.insert("DP-1".to_string(), ViewType::Clock);
+        config.monitor_views.insert("HDMI-1".to_string(), ViewType::MessageBoard);
+
+        let mut all = config.clone();
+        ViewOverrides { view: Some(ViewType::DepartureBoard), ..Default::default() }.apply(&mut all, &monitors);
+        assert!(all.monitor_views.values().all(|v| *v == ViewType::DepartureBoard));
+
+        let mut one = config.clone();
+        ViewOverrides { monitor: Some("HDMI-1".to_string()), ..Default::default() }.apply(&mut one, &monitors);
+        assert_eq!(one.monitor_views["DP-1"], ViewType::Off);
+        assert_eq!(one.monitor_views["HDMI-1"], ViewType::MessageBoard);
+        assert_eq!(single_view(&one, &monitors), ViewType::MessageBoard);
+
+        let mut unknown = config.clone();
+        ViewOverrides { view: Some(ViewType::Off), monitor: Some("VGA-9".to_string()), ..Default::default() }.apply(&mut unknown, &monitors);
+        assert_eq!(unknown.monitor_views, config.monitor_views);
+        assert_eq!(single_view(&config, &monitors), ViewType::Clock);
+    }
+
+    #[test]
+    fn test_ctl_views_outlast_reloads() {
+        let monitors = vec![monitor("DP-1", true), monitor("HDMI-1", false)];
+        let mode = AppMode::Clock { preview: false };
+        let mut overrides = ViewOverrides { view: Some(ViewType::Clock), ..Default::default() };
+        let mut config = AppConfig::default();
+        let command = ControlCommand::SetView { monitor: "HDMI-1".to_string(), view: ViewType::DepartureBoard };
+        apply_control(&command, &mut config, &mut [], &monitors, &mode, &mut overrides).unwrap();
+
+        // What a reload does with the file on disk
+        use_loaded_config(&mut config, AppConfig::default(), &mode, &monitors, &overrides);
+        assert_eq!(config.monitor_views["HDMI-1"], ViewType::DepartureBoard);
+        assert_eq!(config.monitor_views["DP-1"], ViewType::Clock);
+
+        let mut desk = AppConfig::default();
+        assert!(apply_control(&command, &mut desk, &mut [], &monitors, &AppMode::Desk, &mut overrides).is_err());
+        assert!(desk.monitor_views.is_empty());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_virtual_screen_spans_all_monitors() {
+        let mut left = monitor("HDMI-1", false);
+        left.x = -1280;
+        left.y = 200;
+        left.width = 1280;
+        left.height = 1024;
+        let rect = windows_utils::bounding_rect(&[monitor("DP-1", true), left]);
+        assert_eq!((rect.x, rect.y, rect.w, rect.h), (-1280.0, 0.0, 3200.0, 1224.0));
+    }
+
+    #[test]
+    fn test_monitor_rects_follow_desktop_layout() {
+        let mut left = monitor("HDMI-1", false);
+        left.x = -1920;
+        let monitors = vec![monitor("DP-1", true), left];
+        let virtual_rect = Rect::new(-1920.0, 0.0, 3840.0, 1080.0);
+
+        let rects = monitor_rects(&monitors, virtual_rect, Rect::new(0.0, 0.0, 3840.0, 1080.0));
+        assert_eq!(rects, vec![Rect::new(1920.0, 0.0, 1920.0, 1080.0), Rect::new(0.0, 0.0, 1920.0, 1080.0)]);
+
+        // A window at half the desktop's pixel density
+        let rects = monitor_rects(&monitors, virtual_rect, Rect::new(0.0, 0.0, 1920.0, 540.0));
+        assert_eq!(rects[0], Rect::new(960.0, 0.0, 960.0, 540.0));
+    }
+
+    #[test]
+    fn test_fill_monitor_views() {
+        let monitors = vec![monitor("DP-1", true), monitor("HDMI-1", false)];
+        let mut config = AppConfig::default();
+        fill_monitor_views(&mut config, &monitors);
+        assert_eq!(config.monitor_views["DP-1"], ViewType::Clock);
+        assert_eq!(config.monitor_views["HDMI-1"], ViewType::Off);
+
+        // Existing entries are kept, and a config migrated from a single `selected_monitor`
+        // leaves the primary off
+        let mut config = AppConfig::default();
+        config.monitor_views.insert("HDMI-1".to_string(), ViewType::MessageBoard);
+        fill_monitor_views(&mut config, &monitors);
+        assert_eq!(config.monitor_views["DP-1"], ViewType::Off);
+        assert_eq!(config.monitor_views["HDMI-1"], ViewType::MessageBoard);
+    }
+
+    #[test]
+    fn test_desk_geometry_on_screen() {
+        let monitors = vec![monitor("DP-1", true)];
+        let geometry = |x, y| WindowGeometry { x, y, width: 800, height: 300 };
+        assert!(on_screen(&geometry(100, 100), &monitors));
+        assert!(on_screen(&geometry(-700, 900), &monitors));
+        // Left behind on a monitor that has since been unplugged
+        assert!(!on_screen(&geometry(-1500, 100), &monitors));
+        assert!(!on_screen(&WindowGeometry { width: 0, ..geometry(100, 100) }, &monitors));
+    }
+
+    #[test]
+    fn test_monitor_at() {
+        let mut right = monitor("HDMI-1", false);
+        right.x = 1920;
+        let monitors = vec![monitor("DP-1", true), right];
+        assert_eq!(monitor_at(&monitors, vec2(2500.0, 400.0)).map(|m| m.name.as_str()), Some("HDMI-1"));
+        assert!(monitor_at(&monitors, vec2(100.0, 2000.0)).is_none());
+    }
+}
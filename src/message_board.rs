@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::config::{AppConfig, BoardMessage, TextAlign};
+use crate::split_flap;
+use crate::time_source::TimeSource;
+
+/// Wraps and aligns `text` onto a `rows` x `columns` grid of flaps.
+///
+/// Words wrap at spaces, words longer than a row are broken, `\n` starts a new line,
+/// and the block is centered vertically. Text that doesn't fit is cut off.
+pub fn layout(text: &str, rows: usize, columns: usize, align: TextAlign) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in split_flap::normalize_str(paragraph).split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            // Break words that can never fit on one line
+            while word.len() > columns {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                lines.push(word.drain(..columns).collect());
+            }
+
+            let needed = if line.is_empty() { word.len() } else { line.chars().count() + 1 + word.len() };
+            if needed > columns {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.extend(word);
+        }
+        lines.push(line);
+    }
+
+    lines.truncate(rows);
+    let top = (rows - lines.len()) / 2;
+    let mut grid = vec![" ".repeat(columns); rows];
+    for (i, line) in lines.iter().enumerate() {
+        let free = columns - line.chars().count();
+        let left = match align {
+            TextAlign::Left => 0,
+            TextAlign::Center => free / 2,
+            TextAlign::Right => free,
+        };
+        grid[top + i] = format!("{}{}{}", " ".repeat(left), line, " ".repeat(free - left));
+    }
+    grid
+}
+
+/// Rotates through the configured messages on a split-flap grid.
+///
+/// Pushed messages interrupt the rotation, are each shown once, and then the rotation resumes.
+#[derive(Clone)]
+pub struct MessageBoardState {
+    pub lines: Vec<String>,
+    pub prev_lines: Vec<String>,
+    pub columns: usize,
+    pub anim_start: Option<f64>,
+    current: usize,
+    pushed: VecDeque<BoardMessage>,
+    showing_pushed: bool,
+    shown_since: f64,
+    pub time: Rc<dyn TimeSource>,
+}
+
+impl MessageBoardState {
+    pub fn new(time: Rc<dyn TimeSource>, config: &AppConfig) -> Self {
+        let shown_since = time.seconds();
+        let mut s = Self {
+            lines: Vec::new(),
+            prev_lines: Vec::new(),
+            columns: config.message_columns,
+            anim_start: None,
+            current: 0,
+            pushed: VecDeque::new(),
+            showing_pushed: false,
+            shown_since,
+            time,
+        };
+        s.update(config);
+        s.prev_lines = s.lines.clone();
+        s.anim_start = None;
+        s
+    }
+
+    /// Queues a one-off message, shown ahead of the rotation.
+    pub fn push(&mut self, message: BoardMessage) {
+        self.pushed.push_back(message);
+    }
+
+    pub fn update(&mut self, config: &AppConfig) {
+        let now = self.time.seconds();
+        let (rows, columns) = (config.message_rows.max(1), config.message_columns.max(1));
+
+        if self.current >= config.messages.len() {
+            self.current = 0;
+        }
+
+        if !self.showing_pushed && !self.pushed.is_empty() {
+            self.showing_pushed = true;
+            self.shown_since = now;
+        } else if self.showing_pushed {
+            let dwell = self.pushed.front().map(|m| m.dwell_secs).unwrap_or(0);
+            if now - self.shown_since >= dwell as f64 {
+                self.pushed.pop_front();
+                self.showing_pushed = !self.pushed.is_empty();
+                self.shown_since = now;
+            }
+        } else if let Some(message) = config.messages.get(self.current) {
+            if now - self.shown_since >= message.dwell_secs as f64 {
+                self.current = (self.current + 1) % config.messages.len();
+                self.shown_since = now;
+            }
+        }
+
+        let message = if self.showing_pushed { self.pushed.front() } else { config.messages.get(self.current) };
+        let target = match message {
+            Some(message) => layout(&message.text, rows, columns, message.align),
+            None => vec![" ".repeat(columns); rows],
+        };
+
+        if target != self.lines {
+            // A change mid-roll starts from whatever flaps are showing
+            self.prev_lines = match self.anim_start {
+                Some(start) => {
+                    let elapsed = (now - start) * 1000.0;
+                    (0..rows)
+                        .map(|i| {
+                            let from = self.prev_lines.get(i).map(String::as_str).unwrap_or("");
+                            let to = self.lines.get(i).map(String::as_str).unwrap_or("");
+                            split_flap::shown_text(config, from, to, elapsed, i, 0)
+                        })
+                        .collect()
+                }
+                None => std::mem::take(&mut self.lines),
+            };
+            self.lines = target;
+            self.columns = columns;
+            self.anim_start = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BoardMessage;
+    use crate::time_source::FixedClock;
+    use chrono::{DateTime, Duration};
+
+    #[test]
+    fn test_layout_wraps_and_aligns() {
+        let grid = layout("Welcome to the lobby", 4, 10, TextAlign::Left);
+        assert_eq!(grid, vec!["          ", "WELCOME TO", "THE LOBBY ", "          "]);
+
+        let grid = layout("Hi\nthere", 3, 7, TextAlign::Right);
+        assert_eq!(grid, vec!["     HI", "  THERE", "       "]);
+
+        let grid = layout("ok", 1, 6, TextAlign::Center);
+        assert_eq!(grid, vec!["  OK  "]);
+    }
+
+    #[test]
+    fn test_layout_breaks_long_words_and_truncates() {
+        let grid = layout("Supercalifragilistic now", 2, 8, TextAlign::Left);
+        assert_eq!(grid, vec!["SUPERCAL", "IFRAGILI"]);
+    }
+
+    #[test]
+    fn test_messages_rotate_after_dwell() {
+        let config = AppConfig {
+            messages: vec![
+                BoardMessage { dwell_secs: 5, ..BoardMessage::new("One") },
+                BoardMessage { dwell_secs: 2, ..BoardMessage::new("Two") },
+            ],
+            message_rows: 1,
+            message_columns: 5,
+            ..AppConfig::default()
+        };
+        let clock = Rc::new(FixedClock::new(DateTime::parse_from_rfc3339("2024-07-15T09:00:00Z").unwrap()));
+        let mut board = MessageBoardState::new(clock.clone(), &config);
+        assert_eq!(board.lines, vec![" ONE "]);
+        assert!(board.anim_start.is_none());
+
+        clock.advance(Duration::seconds(4));
+        board.update(&config);
+        assert_eq!(board.lines, vec![" ONE "]);
+
+        clock.advance(Duration::seconds(1));
+        board.update(&config);
+        assert_eq!(board.lines, vec![" TWO "]);
+        assert_eq!(board.prev_lines, vec![" ONE "]);
+
+        clock.advance(Duration::seconds(2));
+        board.update(&config);
+        assert_eq!(board.lines, vec![" ONE "]);
+    }
+
+    #[test]
+    fn test_pushed_messages_interrupt_rotation() {
+        let config = AppConfig {
+            messages: vec![BoardMessage { dwell_secs: 60, ..BoardMessage::new("Hello") }],
+            message_rows: 1,
+            message_columns: 5,
+            ..AppConfig::default()
+        };
+        let clock = Rc::new(FixedClock::new(DateTime::parse_from_rfc3339("2024-07-15T09:00:00Z").unwrap()));
+        let mut board = MessageBoardState::new(clock.clone(), &config);
+        assert_eq!(board.lines, vec!["HELLO"]);
+
+        board.push(BoardMessage { dwell_secs: 3, ..BoardMessage::new("Fire") });
+        board.push(BoardMessage { dwell_secs: 3, ..BoardMessage::new("Drill") });
+        board.update(&config);
+        assert_eq!(board.lines, vec!["FIRE "]);
+
+        clock.advance(Duration::seconds(3));
+        board.update(&config);
+        assert_eq!(board.lines, vec!["DRILL"]);
+
+        clock.advance(Duration::seconds(3));
+        board.update(&config);
+        assert_eq!(board.lines, vec!["HELLO"]);
+    }
+}
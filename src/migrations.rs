@@ -0,0 +1,92 @@
+//! Upgrades config files written by older versions, one schema version at a time.
+//!
+//! Files saved before versioning have no `version` field and count as version 0.
+//! To change the schema, bump `CURRENT_VERSION` and append the step that upgrades
+//! the previous version to `MIGRATIONS`, with fixtures in `tests/fixtures/config/`.
+
+use serde_json::{Map, Value};
+
+/// The schema version this build reads and writes.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Upgrades a config by one version.
+pub type Step = fn(&mut Map<String, Value>);
+
+/// `MIGRATIONS[n]` upgrades a version `n` config to version `n + 1`.
+pub const MIGRATIONS: [Step; CURRENT_VERSION as usize] = [
+    v0_selected_monitor,
+];
+
+pub fn version_of(value: &Value) -> u32 {
+    value.get("version").and_then(Value::as_u64).map_or(0, |v| v as u32)
+}
+
+/// Runs every step of `steps` (normally `MIGRATIONS`) from the config's version on.
+/// Configs that aren't JSON objects, or are already current, are left alone.
+pub fn migrate(value: &mut Value, steps: &[Step]) {
+    let from = version_of(value) as usize;
+    let Some(config) = value.as_object_mut() else { return };
+    for (version, step) in steps.iter().enumerate().skip(from) {
+        step(config);
+        config.insert("version".to_string(), Value::from(version as u32 + 1));
+    }
+}
+
+/// Version 0 picked a single clock monitor with `selected_monitor`, before `monitor_views`
+/// existed. It becomes that monitor's entry; the other monitors stay off as they were.
+fn v0_selected_monitor(config: &mut Map<String, Value>) {
+    let selected = config.remove("selected_monitor");
+    let has_views = config
+        .get("monitor_views")
+        .and_then(Value::as_object)
+        .is_some_and(|views| !views.is_empty());
+    match selected {
+        Some(Value::String(name)) if !name.is_empty() && !has_views => {
+            let mut views = Map::new();
+            views.insert(name, Value::from("Clock"));
+            config.insert("monitor_views".to_string(), Value::Object(views));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Value {
+        let path = format!("{}/tests/fixtures/config/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+        let content = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        serde_json::from_str(&content).unwrap()
+    }
+
+    /// Runs one step on `<case>.v<n>.json` and compares the result with `<case>.v<n+1>.json`.
+    fn check_step(version: usize, case: &str) {
+        let mut value = fixture(&format!("{}.v{}", case, version));
+        MIGRATIONS[version](value.as_object_mut().unwrap());
+        let mut expected = fixture(&format!("{}.v{}", case, version + 1));
+        // Steps don't stamp the version; `migrate` does
+        expected.as_object_mut().unwrap().remove("version");
+        assert_eq!(value, expected, "{} v{} -> v{}", case, version, version + 1);
+    }
+
+    #[test]
+    fn test_v0_selected_monitor() {
+        check_step(0, "selected_monitor");
+        check_step(0, "selected_monitor_with_views");
+        check_step(0, "selected_monitor_unset");
+    }
+
+    #[test]
+    fn test_migrate_runs_chain_and_stamps_version() {
+        let mut value = fixture("selected_monitor.v0");
+        migrate(&mut value, &MIGRATIONS);
+        assert_eq!(value, fixture("selected_monitor.v1"));
+        assert_eq!(version_of(&value), CURRENT_VERSION);
+
+        // Current configs pass through untouched
+        let mut current = fixture("selected_monitor.v1");
+        migrate(&mut current, &MIGRATIONS);
+        assert_eq!(current, fixture("selected_monitor.v1"));
+    }
+}
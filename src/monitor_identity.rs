@@ -0,0 +1,375 @@
+//! Recognizing monitors when the OS names them differently.
+//!
+//! Monitor names come from ports or enumeration order (`\\.\DISPLAY2`, `HDMI-1`), and they
+//! move when a laptop docks or a cable changes ports. Settings stay keyed by name, but the
+//! identity behind each name is recorded too, so entries can follow their monitor to a new name.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AppConfig, MonitorSettings, ViewType};
+use crate::windows_utils::MonitorInfo;
+
+/// Make, model and serial from a monitor's EDID.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Edid {
+    pub make: String,  // PNP manufacturer ID, e.g. `DEL`
+    pub model: String, // The monitor name descriptor, else the product code in hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+}
+
+/// What was known about a monitor the last time it was connected.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MonitorIdentity {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edid: Option<Edid>,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+impl MonitorIdentity {
+    /// For setup, e.g. `DEL DELL U2720Q, 3840x2160 at 0,0`.
+    pub fn describe(&self) -> String {
+        let geometry = format!("{}x{} at {},{}", self.width, self.height, self.x, self.y);
+        match &self.edid {
+            Some(edid) => format!("{} {}, {}", edid.make, edid.model, geometry),
+            None => geometry,
+        }
+    }
+}
+
+impl MonitorInfo {
+    pub fn identity(&self) -> MonitorIdentity {
+        MonitorIdentity {
+            edid: self.edid.clone(),
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+            primary: self.is_primary,
+        }
+    }
+}
+
+/// Reads the base block of an EDID; `None` if it isn't one.
+pub fn parse_edid(data: &[u8]) -> Option<Edid> {
+    const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+    if data.len() < 128 || data[..8] != HEADER {
+        return None;
+    }
+
+    // Three letters of five bits each, 1 = 'A'
+    let id = u16::from_be_bytes([data[8], data[9]]);
+    let make: String = [10, 5, 0].iter().map(|shift| (b'@' + ((id >> shift) & 0x1F) as u8) as char).collect();
+    if !make.chars().all(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+    let product = u16::from_le_bytes([data[10], data[11]]);
+    let serial_number = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+
+    let mut name = None;
+    let mut serial = None;
+    for descriptor in data[54..126].chunks(18) {
+        // Display descriptors (rather than timings) start with three zero bytes, then a tag
+        if descriptor[..3] != [0, 0, 0] {
+            continue;
+        }
+        match descriptor[3] {
+            0xFC => name = descriptor_text(&descriptor[5..]),
+            0xFF => serial = descriptor_text(&descriptor[5..]),
+            _ => {}
+        }
+    }
+
+    Some(Edid {
+        make,
+        model: name.unwrap_or_else(|| format!("{:04X}", product)),
+        serial: serial.or_else(|| (serial_number != 0).then(|| serial_number.to_string())),
+    })
+}
+
+/// Descriptor text is up to 13 characters, ended by a newline and padded with spaces.
+fn descriptor_text(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|&b| b == b'\n').unwrap_or(bytes.len());
+    let text = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+const SERIAL: u32 = 100;
+const MODEL: u32 = 50;
+const POSITION: u32 = 15;
+const SIZE: u32 = 10;
+const NAME: u32 = 10;
+const PRIMARY: u32 = 5;
+
+/// How likely the entry saved as `key` belongs to `monitor`; `None` rules it out.
+///
+/// EDIDs decide when both sides have one. Otherwise it takes the same name, or the same
+/// place and size on the desktop.
+fn match_score(key: &str, known: Option<&MonitorIdentity>, monitor: &MonitorInfo) -> Option<u32> {
+    let same_name = key == monitor.name;
+    let Some(known) = known else {
+        // Saved before identities were recorded
+        return same_name.then_some(NAME);
+    };
+
+    let mut score = 0;
+    let mut edid_match = false;
+    if let (Some(a), Some(b)) = (&known.edid, &monitor.edid) {
+        if a.make != b.make || a.model != b.model {
+            return None;
+        }
+        match (&a.serial, &b.serial) {
+            (Some(x), Some(y)) if x != y => return None,
+            (Some(_), Some(_)) => score += SERIAL,
+            _ => score += MODEL,
+        }
+        edid_match = true;
+    }
+    let same_position = (known.x, known.y) == (monitor.x, monitor.y);
+    let same_size = (known.width, known.height) == (monitor.width, monitor.height);
+    score += if same_name { NAME } else { 0 };
+    score += if same_position { POSITION } else { 0 };
+    score += if same_size { SIZE } else { 0 };
+    score += if known.primary == monitor.is_primary { PRIMARY } else { 0 };
+
+    (edid_match || same_name || (same_position && same_size)).then_some(score)
+}
+
+/// Everything saved under one monitor name.
+struct Entry {
+    view: Option<ViewType>,
+    settings: Option<MonitorSettings>,
+    identity: Option<MonitorIdentity>,
+}
+
+fn take_entry(config: &mut AppConfig, key: &str) -> Entry {
+    Entry {
+        view: config.monitor_views.remove(key),
+        settings: config.monitor_settings.remove(key),
+        identity: config.known_monitors.remove(key),
+    }
+}
+
+fn put_entry(config: &mut AppConfig, key: &str, entry: Entry) {
+    if let Some(view) = entry.view {
+        config.monitor_views.insert(key.to_string(), view);
+    }
+    if let Some(settings) = entry.settings {
+        config.monitor_settings.insert(key.to_string(), settings);
+    }
+    if let Some(identity) = entry.identity {
+        config.known_monitors.insert(key.to_string(), identity);
+    }
+}
+
+/// Every monitor name the config has something saved under.
+fn saved_keys(config: &AppConfig) -> Vec<String> {
+    let keys: HashSet<&String> = config.monitor_views.keys()
+        .chain(config.monitor_settings.keys())
+        .chain(config.known_monitors.keys())
+        .collect();
+    let mut keys: Vec<String> = keys.into_iter().cloned().collect();
+    keys.sort();
+    keys
+}
+
+/// Moves saved entries to the names their monitors have now, and records each connected
+/// monitor's identity. Returns whether any entry moved.
+///
+/// Entries for monitors that aren't connected stay as they are, unless a different monitor
+/// now has their name; then they're renamed out of its way.
+pub fn identify_monitors(config: &mut AppConfig, monitors: &[MonitorInfo]) -> bool {
+    let keys = saved_keys(config);
+    let mut candidates = Vec::new();
+    for key in &keys {
+        for (i, monitor) in monitors.iter().enumerate() {
+            if let Some(score) = match_score(key, config.known_monitors.get(key), monitor) {
+                candidates.push((score, key, i));
+            }
+        }
+    }
+    // Best matches claim their monitor first
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)).then(a.2.cmp(&b.2)));
+    let mut matched: HashMap<&String, usize> = HashMap::new();
+    let mut claimed = HashSet::new();
+    for (_, key, i) in candidates {
+        if !matched.contains_key(key) && claimed.insert(i) {
+            matched.insert(key, i);
+        }
+    }
+
+    let entries: Vec<(&String, Entry)> = keys.iter().map(|k| (k, take_entry(config, k))).collect();
+    let mut moved = false;
+    let mut orphans = Vec::new();
+    for (key, entry) in entries {
+        match matched.get(key) {
+            Some(&i) => {
+                moved |= *key != monitors[i].name;
+                put_entry(config, &monitors[i].name, entry);
+            }
+            None => orphans.push((key.clone(), entry)),
+        }
+    }
+    let names: HashSet<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+    for (key, entry) in orphans {
+        let key = if names.contains(key.as_str()) {
+            moved = true;
+            free_key(config, &names, &key)
+        } else {
+            key
+        };
+        put_entry(config, &key, entry);
+    }
+
+    for monitor in monitors {
+        config.known_monitors.insert(monitor.name.clone(), monitor.identity());
+    }
+    moved
+}
+
+/// A name for a disconnected monitor's entry that nothing else uses, e.g. `HDMI-1 (old)`.
+fn free_key(config: &AppConfig, names: &HashSet<&str>, key: &str) -> String {
+    let in_use = |k: &str| {
+        names.contains(k)
+            || config.monitor_views.contains_key(k)
+            || config.monitor_settings.contains_key(k)
+            || config.known_monitors.contains_key(k)
+    };
+    let mut candidate = format!("{} (old)", key);
+    let mut n = 2;
+    while in_use(&candidate) {
+        candidate = format!("{} (old {})", key, n);
+        n += 1;
+    }
+    candidate
+}
+
+/// Names with saved entries but no connected monitor, sorted.
+pub fn orphaned(config: &AppConfig, monitors: &[MonitorInfo]) -> Vec<String> {
+    saved_keys(config)
+        .into_iter()
+        .filter(|key| !monitors.iter().any(|m| m.name == *key))
+        .collect()
+}
+
+/// Gives the connected monitor `to` the view and overrides saved under `from`, and drops `from`.
+pub fn reassign(config: &mut AppConfig, from: &str, to: &str) {
+    let mut entry = take_entry(config, from);
+    // `to` is connected, so it keeps the identity it has now
+    entry.identity = None;
+    put_entry(config, to, entry);
+}
+
+pub fn forget(config: &mut AppConfig, key: &str) {
+    take_entry(config, key);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edid(make: &str, model: &str, serial: Option<&str>) -> Option<Edid> {
+        Some(Edid { make: make.to_string(), model: model.to_string(), serial: serial.map(str::to_string) })
+    }
+
+    fn monitor(name: &str, x: i32, edid: Option<Edid>) -> MonitorInfo {
+        MonitorInfo { name: name.to_string(), x, y: 0, width: 1920, height: 1080, is_primary: x == 0, edid }
+    }
+
+    fn config_for(monitors: &[(&MonitorInfo, ViewType)]) -> AppConfig {
+        let mut config = AppConfig::default();
+        for (m, view) in monitors {
+            config.monitor_views.insert(m.name.clone(), view.clone());
+            config.known_monitors.insert(m.name.clone(), m.identity());
+        }
+        config
+    }
+
+    #[test]
+    fn test_parse_edid() {
+        let mut data = [0u8; 128];
+        data[..8].copy_from_slice(&[0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]);
+        data[8..10].copy_from_slice(&[0x10, 0xAC]); // DEL
+        data[10..12].copy_from_slice(&[0xF5, 0x40]);
+        data[12..16].copy_from_slice(&1234u32.to_le_bytes());
+        data[54..59].copy_from_slice(&[0, 0, 0, 0xFC, 0]);
+        data[59..72].copy_from_slice(b"DELL U2720Q\n ");
+
+        let parsed = parse_edid(&data).unwrap();
+        assert_eq!(parsed, edid("DEL", "DELL U2720Q", Some("1234")).unwrap());
+
+        // A serial descriptor wins over the number; no name gives the product code
+        data[54..59].copy_from_slice(&[0, 0, 0, 0xFF, 0]);
+        data[59..72].copy_from_slice(b"ABC123\n      ");
+        assert_eq!(parse_edid(&data).unwrap(), edid("DEL", "40F5", Some("ABC123")).unwrap());
+
+        data[0] = 1;
+        assert!(parse_edid(&data).is_none());
+        assert!(parse_edid(&data[..100]).is_none());
+    }
+
+    #[test]
+    fn test_entries_follow_renamed_monitors() {
+        let dell = edid("DEL", "DELL U2720Q", Some("A1"));
+        let lg = edid("GSM", "LG HDR 4K", Some("B2"));
+        let before = [monitor(r"\\.\DISPLAY1", 0, dell.clone()), monitor(r"\\.\DISPLAY2", 1920, lg.clone())];
+        let mut config = config_for(&[(&before[0], ViewType::Clock), (&before[1], ViewType::MessageBoard)]);
+
+        // Docking renumbered them; EDIDs outweigh the same place on the desktop
+        let after = [monitor(r"\\.\DISPLAY1", 0, lg), monitor(r"\\.\DISPLAY2", 1920, dell)];
+        assert!(identify_monitors(&mut config, &after));
+        assert_eq!(config.monitor_views[r"\\.\DISPLAY2"], ViewType::Clock);
+        assert_eq!(config.monitor_views[r"\\.\DISPLAY1"], ViewType::MessageBoard);
+        assert_eq!(config.known_monitors[r"\\.\DISPLAY2"].edid, before[0].edid);
+
+        // Nothing moves the second time
+        assert!(!identify_monitors(&mut config, &after));
+    }
+
+    #[test]
+    fn test_without_edid_geometry_decides() {
+        let before = [monitor("DISPLAY1", 0, None), monitor("DISPLAY2", 1920, None)];
+        let mut config = config_for(&[(&before[0], ViewType::DepartureBoard), (&before[1], ViewType::Off)]);
+
+        let after = [monitor("DISPLAY2", 0, None), monitor("DISPLAY1", 1920, None)];
+        identify_monitors(&mut config, &after);
+        assert_eq!(config.monitor_views["DISPLAY2"], ViewType::DepartureBoard);
+        assert_eq!(config.monitor_views["DISPLAY1"], ViewType::Off);
+    }
+
+    #[test]
+    fn test_different_monitor_on_same_port_is_orphaned() {
+        let old = monitor("HDMI-1", 0, edid("DEL", "DELL U2720Q", Some("A1")));
+        let mut config = config_for(&[(&old, ViewType::MessageBoard)]);
+        config.monitor_views.insert("DP-9".to_string(), ViewType::Clock); // Saved before identities
+
+        let new = [monitor("HDMI-1", 0, edid("DEL", "DELL U2720Q", Some("Z9")))];
+        assert!(identify_monitors(&mut config, &new));
+        assert!(!config.monitor_views.contains_key("HDMI-1"));
+        assert_eq!(config.monitor_views["HDMI-1 (old)"], ViewType::MessageBoard);
+        assert_eq!(orphaned(&config, &new), vec!["DP-9".to_string(), "HDMI-1 (old)".to_string()]);
+
+        reassign(&mut config, "HDMI-1 (old)", "HDMI-1");
+        assert_eq!(config.monitor_views["HDMI-1"], ViewType::MessageBoard);
+        assert_eq!(config.known_monitors["HDMI-1"], new[0].identity());
+        forget(&mut config, "DP-9");
+        assert!(orphaned(&config, &new).is_empty());
+    }
+
+    #[test]
+    fn test_legacy_entries_match_by_name() {
+        let mut config = AppConfig::default();
+        config.monitor_views.insert("DP-1".to_string(), ViewType::MessageBoard);
+        let monitors = [monitor("DP-1", 0, edid("DEL", "DELL U2720Q", None))];
+        assert!(!identify_monitors(&mut config, &monitors));
+        assert_eq!(config.monitor_views["DP-1"], ViewType::MessageBoard);
+        assert_eq!(config.known_monitors["DP-1"], monitors[0].identity());
+    }
+}
@@ -0,0 +1,378 @@
+//! Named profiles, and the schedule that switches between them.
+//!
+//! A profile holds only the settings it changes, as they appear in config.json, and is laid
+//! over the main settings while it's active. A profile made by duplicating the main settings
+//! holds all of them, so it doesn't follow later changes to the main settings.
+
+use std::borrow::Cow;
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::config::{AppConfig, SystemConfig};
+
+/// Settings no profile changes: they describe the file, the profiles themselves, or hardware.
+const NOT_IN_PROFILES: [&str; 4] = ["version", "profiles", "profile_rules", "known_monitors"];
+
+/// Whether setup puts `key` in profiles. Locked settings stay out, so they follow the system config.
+fn in_profiles(key: &str, system: &SystemConfig) -> bool {
+    !NOT_IN_PROFILES.contains(&key) && !system.is_locked(key)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub settings: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Day {
+    pub const ALL: [Day; 7] = [Day::Mon, Day::Tue, Day::Wed, Day::Thu, Day::Fri, Day::Sat, Day::Sun];
+
+    fn of(date: &NaiveDateTime) -> Day {
+        Day::ALL[date.weekday().num_days_from_monday() as usize]
+    }
+
+    fn before(self) -> Day {
+        Day::ALL[(self as usize + 6) % 7]
+    }
+}
+
+/// Shows `profile` on `days` from `start` to `end`, local time as `HH:MM`.
+///
+/// An `end` before `start` runs past midnight into the next day; equal times cover the
+/// whole day. No days means every day.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProfileRule {
+    pub profile: String,
+    #[serde(default)]
+    pub days: Vec<Day>,
+    pub start: String,
+    pub end: String,
+}
+
+impl ProfileRule {
+    pub fn times(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        let parse = |text: &str| {
+            NaiveTime::parse_from_str(text.trim(), "%H:%M").map_err(|_| format!("'{}' isn't a time like 09:00", text))
+        };
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    fn on(&self, day: Day) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn covers(&self, now: &NaiveDateTime) -> bool {
+        let Ok((start, end)) = self.times() else { return false };
+        let time = now.time();
+        let today = Day::of(now);
+        if start < end {
+            self.on(today) && start <= time && time < end
+        } else {
+            // Overnight: the evening of a listed day, or the morning after one
+            (self.on(today) && time >= start) || (self.on(today.before()) && time < end)
+        }
+    }
+}
+
+/// The profile the first matching rule picks for `now`, if any.
+pub fn scheduled<'a>(config: &'a AppConfig, now: &NaiveDateTime) -> Option<&'a str> {
+    config.profile_rules.iter()
+        .find(|rule| rule.covers(now) && config.profiles.iter().any(|p| p.name == rule.profile))
+        .map(|rule| rule.profile.as_str())
+}
+
+/// The config with `profile` laid over it.
+pub fn apply<'a>(config: &'a AppConfig, profile: Option<&str>) -> Cow<'a, AppConfig> {
+    match profile.and_then(|name| config.profiles.iter().find(|p| p.name == name)) {
+        Some(profile) if !profile.settings.is_empty() => {
+            overlay(config, profile).map_or(Cow::Borrowed(config), Cow::Owned)
+        }
+        _ => Cow::Borrowed(config),
+    }
+}
+
+fn overlay(config: &AppConfig, profile: &Profile) -> Result<AppConfig, serde_json::Error> {
+    let Value::Object(mut merged) = serde_json::to_value(config)? else { unreachable!() };
+    for (key, value) in &profile.settings {
+        if !NOT_IN_PROFILES.contains(&key.as_str()) {
+            merged.insert(key.clone(), value.clone());
+        }
+    }
+    serde_json::from_value(Value::Object(merged))
+}
+
+/// The first profile whose settings don't fit the config, as `(profiles[i].settings, error)`.
+pub fn check(config: &AppConfig) -> Result<(), (String, String)> {
+    for (i, profile) in config.profiles.iter().enumerate() {
+        if let Err(e) = overlay(config, profile) {
+            return Err((format!("profiles[{}].settings", i), e.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// `base` with a number added until no profile has the name, e.g. `Night 2`.
+pub fn free_name(config: &AppConfig, base: &str) -> String {
+    let taken = |name: &str| config.profiles.iter().any(|p| p.name == name);
+    let mut name = base.to_string();
+    let mut n = 2;
+    while taken(&name) {
+        name = format!("{} {}", base, n);
+        n += 1;
+    }
+    name
+}
+
+/// Adds a profile that changes nothing yet. Returns its name.
+pub fn create(config: &mut AppConfig) -> String {
+    let name = free_name(config, "New profile");
+    config.profiles.push(Profile { name: name.clone(), settings: Map::new() });
+    name
+}
+
+/// Adds a profile with the settings of `from`, or all of the main settings that aren't locked
+/// if `None`. Returns its name.
+pub fn duplicate(config: &mut AppConfig, from: Option<&str>, system: &SystemConfig) -> String {
+    let settings = match from.and_then(|name| config.profiles.iter().find(|p| p.name == name)) {
+        Some(profile) => profile.settings.clone(),
+        None => match serde_json::to_value(&*config) {
+            Ok(Value::Object(mut all)) => {
+                all.retain(|key, _| in_profiles(key, system));
+                all
+            }
+            _ => Map::new(),
+        },
+    };
+    let name = free_name(config, &format!("{} copy", from.unwrap_or("Default")));
+    config.profiles.push(Profile { name: name.clone(), settings });
+    name
+}
+
+/// Renames a profile and the rules that use it.
+pub fn rename(config: &mut AppConfig, from: &str, to: &str) -> Result<(), String> {
+    let to = to.trim();
+    if to.is_empty() {
+        return Err("The name can't be empty".to_string());
+    }
+    if to != from && config.profiles.iter().any(|p| p.name == to) {
+        return Err(format!("There's already a profile named '{}'", to));
+    }
+    for profile in config.profiles.iter_mut().filter(|p| p.name == from) {
+        profile.name = to.to_string();
+    }
+    for rule in config.profile_rules.iter_mut().filter(|r| r.profile == from) {
+        rule.profile = to.to_string();
+    }
+    Ok(())
+}
+
+/// Removes a profile and the rules that use it.
+pub fn delete(config: &mut AppConfig, name: &str) {
+    config.profiles.retain(|p| p.name != name);
+    config.profile_rules.retain(|r| r.profile != name);
+}
+
+/// Setup's state while a profile is edited. Setup edits a working copy showing the profile
+/// laid over the main settings; what differs from the main settings goes into the profile.
+pub struct ProfileEdit {
+    pub name: String,
+    /// The config as saved, with the main settings.
+    pub base: AppConfig,
+    system: SystemConfig,
+}
+
+impl ProfileEdit {
+    /// Turns `config` into the working copy for `name`.
+    pub fn start(config: &mut AppConfig, name: &str, system: &SystemConfig) -> Self {
+        let base = config.clone();
+        *config = apply(&base, Some(name)).into_owned();
+        Self { name: name.to_string(), base, system: system.clone() }
+    }
+
+    /// Stores the working copy's changes in the profile. Settings the profile already
+    /// had stay in it, even when they now match the main settings.
+    pub fn sync(&mut self, working: &AppConfig) {
+        self.base.known_monitors = working.known_monitors.clone();
+        let (Ok(Value::Object(main)), Ok(Value::Object(edited))) = (serde_json::to_value(&self.base), serde_json::to_value(working)) else {
+            return;
+        };
+        let Some(profile) = self.base.profiles.iter_mut().find(|p| p.name == self.name) else { return };
+        for (key, value) in edited {
+            if !in_profiles(&key, &self.system) {
+                continue;
+            }
+            if profile.settings.contains_key(&key) || main.get(&key) != Some(&value) {
+                profile.settings.insert(key, value);
+            }
+        }
+    }
+
+    /// Puts the saved config back in place of the working copy.
+    pub fn finish(mut self, working: &mut AppConfig) {
+        self.sync(working);
+        *working = self.base;
+    }
+}
+
+/// Switches setup to editing `name`, or the main settings if `None`.
+pub fn select(working: &mut AppConfig, editing: &mut Option<ProfileEdit>, name: Option<&str>, system: &SystemConfig) {
+    if let Some(edit) = editing.take() {
+        edit.finish(working);
+    }
+    *editing = name.map(|name| ProfileEdit::start(working, name, system));
+}
+
+/// The config as it's saved: the working copy, unless a profile is being edited.
+pub fn saved<'a>(working: &'a mut AppConfig, editing: &'a mut Option<ProfileEdit>) -> &'a mut AppConfig {
+    match editing {
+        Some(edit) => &mut edit.base,
+        None => working,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, time: &str) -> NaiveDateTime {
+        // June 2026 starts on a Monday
+        NaiveDate::from_ymd_opt(2026, 6, day).unwrap().and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+    }
+
+    fn rule(profile: &str, days: &[Day], start: &str, end: &str) -> ProfileRule {
+        ProfileRule { profile: profile.to_string(), days: days.to_vec(), start: start.to_string(), end: end.to_string() }
+    }
+
+    fn profile(name: &str, settings: Value) -> Profile {
+        let Value::Object(settings) = settings else { panic!() };
+        Profile { name: name.to_string(), settings }
+    }
+
+    #[test]
+    fn test_schedule() {
+        let weekdays = [Day::Mon, Day::Tue, Day::Wed, Day::Thu, Day::Fri];
+        let mut config = AppConfig {
+            profiles: vec![profile("Office hours", serde_json::json!({})), profile("Night", serde_json::json!({}))],
+            profile_rules: vec![
+                rule("Office hours", &weekdays, "09:00", "18:00"),
+                rule("Night", &[Day::Fri], "22:00", "06:00"),
+                rule("Missing", &[], "00:00", "00:00"),
+            ],
+            ..AppConfig::default()
+        };
+
+        assert_eq!(scheduled(&config, &at(1, "09:00")), Some("Office hours"));
+        assert_eq!(scheduled(&config, &at(5, "17:59")), Some("Office hours"));
+        assert_eq!(scheduled(&config, &at(5, "18:00")), None);
+        assert_eq!(scheduled(&config, &at(6, "10:00")), None); // Saturday
+        // Friday night runs into Saturday morning, but not Friday morning
+        assert_eq!(scheduled(&config, &at(5, "23:30")), Some("Night"));
+        assert_eq!(scheduled(&config, &at(6, "05:59")), Some("Night"));
+        assert_eq!(scheduled(&config, &at(5, "05:00")), None);
+
+        config.profile_rules[0].start = "9am".to_string();
+        assert_eq!(scheduled(&config, &at(1, "10:00")), None);
+        assert!(config.profile_rules[0].times().is_err());
+    }
+
+    #[test]
+    fn test_apply_overlays_settings() {
+        let mut config = AppConfig { scale: 0.8, ..AppConfig::default() };
+        config.profiles.push(profile("Night", serde_json::json!({ "bg_color": [0.0, 0.0, 0.0], "show_seconds": false })));
+
+        let night = apply(&config, Some("Night"));
+        assert_eq!(night.bg_color, [0.0, 0.0, 0.0]);
+        assert!(!night.show_seconds);
+        assert_eq!(night.scale, 0.8);
+        assert!(matches!(apply(&config, None), Cow::Borrowed(_)));
+        assert!(matches!(apply(&config, Some("Unknown")), Cow::Borrowed(_)));
+
+        config.profiles.push(profile("Broken", serde_json::json!({ "scale": "big" })));
+        assert_eq!(check(&config).unwrap_err().0, "profiles[1].settings");
+    }
+
+    #[test]
+    fn test_editing_a_profile_keeps_only_its_changes() {
+        let mut config = AppConfig::default();
+        config.profiles.push(profile("Night", serde_json::json!({ "show_seconds": false })));
+
+        let mut editing = None;
+        select(&mut config, &mut editing, Some("Night"), &SystemConfig::default());
+        assert!(!config.show_seconds);
+        config.scale = 0.3;
+        config.show_seconds = true; // Same as the main settings, but the profile had it
+        select(&mut config, &mut editing, None, &SystemConfig::default());
+        assert!(editing.is_none());
+
+        assert_eq!(config.scale, AppConfig::default().scale);
+        let settings = &config.profiles[0].settings;
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings["show_seconds"], Value::Bool(true));
+        assert!(settings.contains_key("scale"));
+    }
+
+    #[test]
+    fn test_profiles_leave_locked_settings_alone() {
+        let system = SystemConfig::locking(serde_json::json!({ "scale": 0.5 }));
+        let mut config = AppConfig { scale: 0.5, ..AppConfig::default() };
+        config.profiles.push(profile("Night", serde_json::json!({ "scale": 0.9, "show_seconds": false })));
+
+        // A profile from before the lock is overruled where it's shown
+        let mut night = apply(&config, Some("Night")).into_owned();
+        system.enforce(&mut night);
+        assert_eq!(night.scale, 0.5);
+        assert!(!night.show_seconds);
+
+        config.profiles.clear();
+        duplicate(&mut config, None, &system);
+        assert!(!config.profiles[0].settings.contains_key("scale"));
+        assert!(config.profiles[0].settings.contains_key("show_seconds"));
+
+        config.profiles.clear();
+        config.profiles.push(profile("Night", serde_json::json!({})));
+        let mut editing = None;
+        select(&mut config, &mut editing, Some("Night"), &system);
+        config.scale = 0.9;
+        config.pixelated = true;
+        select(&mut config, &mut editing, None, &system);
+        assert_eq!(config.profiles[0].settings.keys().collect::<Vec<_>>(), vec!["pixelated"]);
+    }
+
+    #[test]
+    fn test_duplicate_rename_delete() {
+        let mut config = AppConfig::default();
+        assert_eq!(create(&mut config), "New profile");
+        assert_eq!(create(&mut config), "New profile 2");
+        config.profiles.clear();
+        let snapshot = duplicate(&mut config, None, &SystemConfig::default());
+        assert_eq!(snapshot, "Default copy");
+        assert!(config.profiles[0].settings.contains_key("scale"));
+        assert!(!config.profiles[0].settings.contains_key("profiles"));
+
+        config.profile_rules.push(rule("Default copy", &[], "08:00", "09:00"));
+        assert_eq!(duplicate(&mut config, Some("Default copy"), &SystemConfig::default()), "Default copy copy");
+        assert!(rename(&mut config, "Default copy", "Default copy copy").is_err());
+        rename(&mut config, "Default copy", "Weekend").unwrap();
+        assert_eq!(config.profile_rules[0].profile, "Weekend");
+
+        delete(&mut config, "Weekend");
+        assert_eq!(config.profiles.len(), 1);
+        assert!(config.profile_rules.is_empty());
+        assert_eq!(free_name(&config, "Default copy copy"), "Default copy copy 2");
+    }
+}
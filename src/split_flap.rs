@@ -0,0 +1,166 @@
+use crate::config::{AppConfig, FlapStagger};
+
+/// The flaps on every departure board cell, in the order they roll past.
+pub const ALPHABET: &[char] = &[
+    ' ',
+    'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M',
+    'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
+    '.', ',', ':', '-', '+', '/', '\'', '(', ')', '!', '?', '&', '#', '@',
+];
+
+/// Shown for characters that have no flap and no close equivalent.
+pub const UNKNOWN_FLAP: char = ' ';
+
+/// Maps a character onto the flap alphabet (uppercase, accents stripped).
+pub fn normalize(c: char) -> char {
+    let c = match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' => 'A',
+        'ç' | 'Ç' => 'C',
+        'è' | 'é' | 'ê' | 'ë' | 'È' | 'É' | 'Ê' | 'Ë' => 'E',
+        'ì' | 'í' | 'î' | 'ï' | 'Ì' | 'Í' | 'Î' | 'Ï' => 'I',
+        'ñ' | 'Ñ' => 'N',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' => 'O',
+        'ù' | 'ú' | 'û' | 'ü' | 'Ù' | 'Ú' | 'Û' | 'Ü' => 'U',
+        'ý' | 'ÿ' | 'Ý' => 'Y',
+        '"' | '‘' | '’' => '\'',
+        '–' | '—' | '_' => '-',
+        c => c.to_ascii_uppercase(),
+    };
+    if ALPHABET.contains(&c) { c } else { UNKNOWN_FLAP }
+}
+
+pub fn normalize_str(s: &str) -> String {
+    s.chars().map(normalize).collect()
+}
+
+fn index_of(c: char) -> usize {
+    ALPHABET.iter().position(|a| *a == c).unwrap_or(0)
+}
+
+/// Number of flaps that pass when rolling forward from `from` to `to`.
+pub fn steps(from: char, to: char) -> usize {
+    let len = ALPHABET.len();
+    (index_of(to) + len - index_of(from)) % len
+}
+
+/// What a single cell shows at one moment of its animation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Flap {
+    /// The flap currently facing the viewer.
+    pub current: char,
+    /// The flap falling into place.
+    pub next: char,
+    /// Progress of the falling flap (0.0 - 1.0).
+    pub progress: f32,
+}
+
+impl Flap {
+    fn at_rest(c: char) -> Self {
+        Self { current: c, next: c, progress: 1.0 }
+    }
+}
+
+/// Rolls a cell forward through the alphabet, one flap every `step_ms`.
+pub fn roll(from: char, to: char, elapsed_ms: f64, step_ms: f64) -> Flap {
+    let (from, to) = (normalize(from), normalize(to));
+    let total = steps(from, to);
+    if total == 0 || elapsed_ms >= total as f64 * step_ms {
+        return Flap::at_rest(to);
+    }
+    if elapsed_ms <= 0.0 {
+        return Flap::at_rest(from);
+    }
+
+    let position = elapsed_ms / step_ms.max(1.0);
+    let done = position.floor() as usize;
+    let start = index_of(from);
+    Flap {
+        current: ALPHABET[(start + done) % ALPHABET.len()],
+        next: ALPHABET[(start + done + 1) % ALPHABET.len()],
+        progress: position.fract() as f32,
+    }
+}
+
+/// Animation state of a cell, honoring the roll and stagger settings.
+///
+/// `elapsed_ms` is measured from the row's change; `row`/`column` pick the stagger delay.
+pub fn flap_at(config: &AppConfig, from: char, to: char, elapsed_ms: f64, row: usize, column: usize) -> Flap {
+    let delay = match config.flap_stagger {
+        FlapStagger::None => 0,
+        FlapStagger::Column => column,
+        FlapStagger::Row => row,
+    } as f64 * config.flap_stagger_ms as f64;
+    let elapsed = elapsed_ms - delay;
+
+    if config.flap_roll {
+        return roll(from, to, elapsed, config.flap_step_ms as f64);
+    }
+
+    // Single flip straight to the target
+    if from == to || elapsed >= config.animation_speed as f64 {
+        return Flap::at_rest(to);
+    }
+    let progress = (elapsed / config.animation_speed as f64).max(0.0) as f32;
+    Flap { current: from, next: to, progress }
+}
+
+/// The text a row of flaps is showing `elapsed_ms` into its change from `from` to `to`.
+///
+/// Used to start a new change from wherever a running one has got to.
+pub fn shown_text(config: &AppConfig, from: &str, to: &str, elapsed_ms: f64, row: usize, first_column: usize) -> String {
+    let len = from.chars().count().max(to.chars().count());
+    (0..len)
+        .map(|j| {
+            let f = from.chars().nth(j).unwrap_or(' ');
+            let t = to.chars().nth(j).unwrap_or(' ');
+            flap_at(config, f, t, elapsed_ms, row, first_column + j).current
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize_str("Zürich – São Paulo"), "ZURICH - SAO PAULO");
+        assert_eq!(normalize('é'), 'E');
+        assert_eq!(normalize('東'), UNKNOWN_FLAP);
+        assert!(ALPHABET.contains(&':'));
+    }
+
+    #[test]
+    fn test_roll_steps_through_intermediate_flaps() {
+        assert_eq!(steps('A', 'D'), 3);
+        assert_eq!(roll('A', 'D', 0.0, 50.0), Flap::at_rest('A'));
+
+        let mid = roll('A', 'D', 75.0, 50.0);
+        assert_eq!((mid.current, mid.next), ('B', 'C'));
+        assert!((mid.progress - 0.5).abs() < 1e-6);
+
+        assert_eq!(roll('A', 'D', 150.0, 50.0), Flap::at_rest('D'));
+    }
+
+    #[test]
+    fn test_roll_wraps_around_alphabet() {
+        // '9' to '0' has to go all the way round
+        assert_eq!(steps('9', '0'), ALPHABET.len() - 9);
+        let flap = roll('9', '0', 10.0, 50.0);
+        assert_eq!((flap.current, flap.next), ('9', '.'));
+        let flap = roll('@', 'A', 10.0, 50.0);
+        assert_eq!((flap.current, flap.next), ('@', ' '));
+    }
+
+    #[test]
+    fn test_stagger_delays_start() {
+        let config = AppConfig { flap_stagger: FlapStagger::Column, flap_stagger_ms: 100, flap_step_ms: 50, ..AppConfig::default() };
+        assert_eq!(flap_at(&config, 'A', 'B', 120.0, 0, 0), Flap::at_rest('B'));
+        assert_eq!(flap_at(&config, 'A', 'B', 120.0, 0, 3), Flap::at_rest('A'));
+
+        let config = AppConfig { flap_stagger: FlapStagger::Row, ..config };
+        assert_eq!(flap_at(&config, 'A', 'B', 120.0, 0, 3), Flap::at_rest('B'));
+        assert_eq!(flap_at(&config, 'A', 'B', 120.0, 2, 0), Flap::at_rest('A'));
+    }
+}
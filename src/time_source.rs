@@ -0,0 +1,285 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, Utc};
+#[cfg(test)]
+use chrono_tz::Tz;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Where the clock gets "now" from.
+///
+/// Everything that shows or animates time reads it through this trait, so a
+/// fixed or accelerated source can be swapped in to demo or test midnight,
+/// DST changes and 12h rollovers without waiting for them.
+pub trait TimeSource {
+    /// Current wall-clock instant.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Current local wall-clock time. Defaults to the system time zone.
+    fn now_local(&self) -> DateTime<FixedOffset> {
+        self.now().with_timezone(&Local).fixed_offset()
+    }
+
+    /// Monotonic seconds used to drive animations.
+    fn seconds(&self) -> f64;
+}
+
+/// The real system clock.
+pub struct SystemClock {
+    started: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self { started: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn seconds(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+}
+
+/// The time zone a fixed clock shows its instant in.
+#[derive(Clone, Copy)]
+enum Zone {
+    System,
+    #[cfg(test)]
+    Named(Tz),
+    /// One offset whatever the date, so tests read the same in every zone.
+    #[cfg(test)]
+    Pinned(FixedOffset),
+}
+
+/// A clock frozen at a given instant until it is explicitly stepped.
+pub struct FixedClock {
+    now: Cell<DateTime<Utc>>,
+    zone: Zone,
+    seconds: Cell<f64>,
+}
+
+impl FixedClock {
+    /// Shows `local`'s own UTC offset, even when stepped across a DST change.
+    #[cfg(test)]
+    pub fn new(local: DateTime<FixedOffset>) -> Self {
+        Self::with_zone(local.with_timezone(&Utc), Zone::Pinned(*local.offset()))
+    }
+
+    /// Shows the system time zone, DST changes included.
+    pub fn local(now: DateTime<Utc>) -> Self {
+        Self::with_zone(now, Zone::System)
+    }
+
+    #[cfg(test)]
+    pub fn in_zone(now: DateTime<Utc>, zone: Tz) -> Self {
+        Self::with_zone(now, Zone::Named(zone))
+    }
+
+    fn with_zone(now: DateTime<Utc>, zone: Zone) -> Self {
+        Self { now: Cell::new(now), zone, seconds: Cell::new(0.0) }
+    }
+
+    /// Moves both the wall clock and the animation timeline forward.
+    #[cfg(test)]
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+        let secs = by.num_milliseconds() as f64 / 1000.0;
+        self.seconds.set(self.seconds.get() + secs);
+    }
+}
+
+impl TimeSource for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.now.get()
+    }
+
+    fn now_local(&self) -> DateTime<FixedOffset> {
+        let now = self.now.get();
+        match self.zone {
+            Zone::System => now.with_timezone(&Local).fixed_offset(),
+            #[cfg(test)]
+            Zone::Named(zone) => now.with_timezone(&zone).fixed_offset(),
+            #[cfg(test)]
+            Zone::Pinned(offset) => now.with_timezone(&offset),
+        }
+    }
+
+    fn seconds(&self) -> f64 {
+        self.seconds.get()
+    }
+}
+
+/// The system clock shifted by a constant amount.
+pub struct OffsetClock {
+    offset: Duration,
+    system: SystemClock,
+}
+
+impl OffsetClock {
+    pub fn new(offset: Duration) -> Self {
+        Self { offset, system: SystemClock::new() }
+    }
+}
+
+impl TimeSource for OffsetClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now() + self.offset
+    }
+
+    fn seconds(&self) -> f64 {
+        self.system.seconds()
+    }
+}
+
+/// A clock starting at `start` that runs `factor` times faster than real time.
+///
+/// Animation time stays real-time so flips remain watchable.
+pub struct AcceleratedClock {
+    start: DateTime<Utc>,
+    factor: f64,
+    system: SystemClock,
+}
+
+impl AcceleratedClock {
+    pub fn new(start: DateTime<Utc>, factor: f64) -> Self {
+        Self { start, factor, system: SystemClock::new() }
+    }
+}
+
+impl TimeSource for AcceleratedClock {
+    fn now(&self) -> DateTime<Utc> {
+        scaled(self.system.seconds(), self.factor)
+            .and_then(|elapsed| self.start.checked_add_signed(elapsed))
+            .unwrap_or(if self.factor < 0.0 { DateTime::<Utc>::MIN_UTC } else { DateTime::<Utc>::MAX_UTC })
+    }
+
+    fn seconds(&self) -> f64 {
+        self.system.seconds()
+    }
+}
+
+/// `secs` of real time at `factor` times the speed, if that fits a `Duration`.
+fn scaled(secs: f64, factor: f64) -> Option<Duration> {
+    let ms = secs * factor * 1000.0;
+    if !ms.is_finite() || ms.abs() >= i64::MAX as f64 {
+        return None;
+    }
+    Duration::try_milliseconds(ms as i64)
+}
+
+/// How long an accelerated clock has to keep running, in real seconds, to be accepted.
+const ACCEL_RUN_SECS: f64 = 365.0 * 24.0 * 3600.0;
+
+/// Builds a time source from a spec string:
+///
+/// - `system`
+/// - `fixed:<RFC 3339>` e.g. `fixed:2024-03-10T01:59:50-05:00`, shown in the local time zone
+///   (set `TZ` to watch another zone's DST change)
+/// - `offset:<seconds>` e.g. `offset:-3600`
+/// - `accel:<factor>[@<RFC 3339>]` e.g. `accel:60@2024-12-31T23:58:00Z`
+pub fn parse_time_source(spec: &str) -> Result<Rc<dyn TimeSource>, String> {
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+    let parse_instant = |s: &str| {
+        DateTime::parse_from_rfc3339(s).map_err(|e| format!("Invalid time '{}': {}", s, e))
+    };
+
+    match kind.trim().to_lowercase().as_str() {
+        "system" => Ok(Rc::new(SystemClock::new())),
+        "fixed" => Ok(Rc::new(FixedClock::local(parse_instant(arg)?.with_timezone(&Utc)))),
+        "offset" => {
+            let invalid = || format!("Invalid offset '{}'", arg);
+            let secs: i64 = arg.trim().parse().map_err(|_| invalid())?;
+            let offset = Duration::try_seconds(secs)
+                .filter(|offset| Utc::now().checked_add_signed(*offset).is_some())
+                .ok_or_else(invalid)?;
+            Ok(Rc::new(OffsetClock::new(offset)))
+        }
+        "accel" => {
+            let (factor, start) = match arg.split_once('@') {
+                Some((f, s)) => (f, parse_instant(s)?.with_timezone(&Utc)),
+                None => (arg, Utc::now()),
+            };
+            let invalid = || format!("Invalid factor '{}'", factor);
+            let factor: f64 = factor.trim().parse().map_err(|_| invalid())?;
+            // A year from now at this speed has to be a time the clock can show
+            scaled(ACCEL_RUN_SECS, factor)
+                .and_then(|run| start.checked_add_signed(run))
+                .ok_or_else(invalid)?;
+            Ok(Rc::new(AcceleratedClock::new(start, factor)))
+        }
+        other => Err(format!("Unknown time source '{}'", other)),
+    }
+}
+
+/// Time source for this run: `FLIP_CLOCK_TIME` if set and valid, else the system clock.
+pub fn from_env() -> Rc<dyn TimeSource> {
+    if let Ok(spec) = std::env::var("FLIP_CLOCK_TIME") {
+        match parse_time_source(&spec) {
+            Ok(source) => return source,
+            Err(e) => eprintln!("Warning: FLIP_CLOCK_TIME ignored: {}", e),
+        }
+    }
+    Rc::new(SystemClock::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    #[test]
+    fn test_fixed_clock_advance() {
+        let start = DateTime::parse_from_rfc3339("2024-12-31T23:59:59+01:00").unwrap();
+        let clock = FixedClock::new(start);
+        clock.advance(Duration::seconds(1));
+
+        let local = clock.now_local();
+        assert_eq!((local.hour(), local.minute(), local.second()), (0, 0, 0));
+        assert_eq!(clock.now().hour(), 23);
+        assert!((clock.seconds() - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_parse_time_source() {
+        let fixed = parse_time_source("fixed:2024-03-10T01:59:50-05:00").unwrap();
+        assert_eq!(fixed.now(), DateTime::parse_from_rfc3339("2024-03-10T06:59:50Z").unwrap());
+
+        let offset = parse_time_source("offset:3600").unwrap();
+        let diff = offset.now() - Utc::now();
+        assert!((diff.num_seconds() - 3600).abs() <= 1);
+
+        let accel = parse_time_source("accel:60@2024-01-01T00:00:00Z").unwrap();
+        assert!(accel.now() >= DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap());
+
+        assert!(parse_time_source("fixed:yesterday").is_err());
+        assert!(parse_time_source("sundial").is_err());
+        assert!(parse_time_source("offset:9223372036854775807").is_err());
+        assert!(parse_time_source("offset:99999999999999").is_err());
+        assert!(parse_time_source("accel:1e300").is_err());
+        assert!(parse_time_source("accel:NaN").is_err());
+        assert!(parse_time_source("accel:-1e12@2024-01-01T00:00:00Z").is_err());
+    }
+
+    #[test]
+    fn test_fixed_clock_follows_dst() {
+        // New York springs forward at 02:00 EST on 10 March 2024
+        let start = DateTime::parse_from_rfc3339("2024-03-10T01:59:50-05:00").unwrap().with_timezone(&Utc);
+        let clock = FixedClock::in_zone(start, chrono_tz::America::New_York);
+        assert_eq!(clock.now_local().offset().local_minus_utc(), -5 * 3600);
+
+        clock.advance(Duration::seconds(10));
+        let local = clock.now_local();
+        assert_eq!((local.hour(), local.minute()), (3, 0));
+        assert_eq!(local.offset().local_minus_utc(), -4 * 3600);
+    }
+}
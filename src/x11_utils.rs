@@ -0,0 +1,488 @@
+//! Monitor layout and full-screen spanning through the X server.
+//!
+//! Our window is always an X11 window, so on a Wayland desktop this talks to
+//! XWayland, which mirrors the compositor's outputs (names and layout included).
+
+use std::sync::Mutex;
+
+use macroquad::prelude::Rect;
+use x11rb::connection::Connection;
+use x11rb::protocol::randr::{ConnectionExt as _, Output};
+use x11rb::protocol::xinerama::ConnectionExt as _;
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ClientMessageEvent, ConfigureWindowAux, ConnectionExt as _,
+    EventMask, InputFocus, PropMode, StackMode, Window,
+};
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::CURRENT_TIME;
+
+use crate::config::WindowGeometry;
+use crate::monitor_identity::{parse_edid, Edid};
+use crate::windows_utils::MonitorInfo;
+
+type Error = Box<dyn std::error::Error>;
+
+/// Monitors on the default screen of `$DISPLAY`, or `None` if no X server is reachable.
+///
+/// Uses RandR 1.5 monitors, then Xinerama, then the whole screen as one monitor.
+pub fn get_monitors() -> Option<Vec<MonitorInfo>> {
+    let (conn, screen_num) = x11rb::connect(None).ok()?;
+    let screen = &conn.setup().roots[screen_num];
+
+    let mut monitors = match randr_monitors(&conn, screen.root) {
+        Ok(monitors) if !monitors.is_empty() => monitors,
+        _ => match xinerama_monitors(&conn) {
+            Ok(monitors) if !monitors.is_empty() => monitors,
+            _ => vec![MonitorInfo {
+                name: format!("SCREEN-{}", screen_num),
+                x: 0,
+                y: 0,
+                width: screen.width_in_pixels as i32,
+                height: screen.height_in_pixels as i32,
+                is_primary: true,
+                edid: None,
+            }],
+        },
+    };
+    ensure_primary(&mut monitors);
+    Some(monitors)
+}
+
+fn randr_monitors(conn: &impl Connection, root: Window) -> Result<Vec<MonitorInfo>, Error> {
+    // RRGetMonitors needs 1.5; older servers fall through to Xinerama
+    let version = conn.randr_query_version(1, 5)?.reply()?;
+    if (version.major_version, version.minor_version) < (1, 5) {
+        return Ok(Vec::new());
+    }
+
+    let reply = conn.randr_get_monitors(root, true)?.reply()?;
+    let edid_atom = atom(conn, "EDID")?;
+    let mut monitors = Vec::new();
+    for m in reply.monitors {
+        let name = conn.get_atom_name(m.name)?.reply()?;
+        monitors.push(MonitorInfo {
+            name: String::from_utf8_lossy(&name.name).into_owned(),
+            x: m.x as i32,
+            y: m.y as i32,
+            width: m.width as i32,
+            height: m.height as i32,
+            is_primary: m.primary,
+            edid: m.outputs.first().and_then(|&output| output_edid(conn, output, edid_atom)),
+        });
+    }
+    Ok(monitors)
+}
+
+/// The EDID the driver read from an output's monitor, if it exposes one.
+fn output_edid(conn: &impl Connection, output: Output, edid_atom: Atom) -> Option<Edid> {
+    // The base block is all we need: 128 bytes, in 4-byte units
+    let reply = conn.randr_get_output_property(output, edid_atom, AtomEnum::ANY, 0, 32, false, false).ok()?.reply().ok()?;
+    parse_edid(&reply.data)
+}
+
+fn xinerama_monitors(conn: &impl Connection) -> Result<Vec<MonitorInfo>, Error> {
+    if conn.xinerama_is_active()?.reply()?.state == 0 {
+        return Ok(Vec::new());
+    }
+    let reply = conn.xinerama_query_screens()?.reply()?;
+    Ok(reply.screen_info.iter().enumerate()
+        .map(|(i, s)| MonitorInfo {
+            name: format!("XINERAMA-{}", i),
+            x: s.x_org as i32,
+            y: s.y_org as i32,
+            width: s.width as i32,
+            height: s.height as i32,
+            is_primary: false,
+            edid: None,
+        })
+        .collect())
+}
+
+/// How our window was placed before it was stretched over every monitor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SavedWindow {
+    window: Window,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    /// Covered by bypassing the window manager rather than asking it.
+    override_redirect: bool,
+}
+
+static SAVED: Mutex<Option<SavedWindow>> = Mutex::new(None);
+
+/// Stretches our window borderless across the whole virtual screen.
+///
+/// An EWMH window manager is asked via `_NET_WM_FULLSCREEN_MONITORS`; without one
+/// (or if it doesn't support that) the window is made override-redirect and placed directly.
+pub fn cover_virtual_screen() {
+    let result = x11rb::connect(None).map_err(Error::from).and_then(|(conn, screen_num)| {
+        let root = conn.setup().roots[screen_num].root;
+        let window = find_window(&conn, root, crate::WM_CLASS)?.ok_or("window not found")?;
+        let saved = cover(&conn, root, window)?;
+        *SAVED.lock().unwrap() = Some(saved);
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: Could not cover all monitors: {}", e);
+    }
+}
+
+/// Puts the window back where it was before `cover_virtual_screen`.
+pub fn restore_window() {
+    let Some(saved) = SAVED.lock().unwrap().take() else { return };
+    let result = x11rb::connect(None).map_err(Error::from).and_then(|(conn, screen_num)| {
+        restore(&conn, conn.setup().roots[screen_num].root, saved)
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: Could not restore the window: {}", e);
+    }
+}
+
+/// Moves our window into an xscreensaver window, or over the root window if `parent` is `None`.
+///
+/// Returns the area it now fills, in desktop coordinates.
+pub fn embed_in(parent: Option<Window>) -> Option<Rect> {
+    let result = x11rb::connect(None).map_err(Error::from).and_then(|(conn, screen_num)| {
+        let root = conn.setup().roots[screen_num].root;
+        let window = find_window(&conn, root, crate::WM_CLASS)?.ok_or("window not found")?;
+        match parent {
+            Some(parent) => reparent(&conn, root, window, parent),
+            None => {
+                *SAVED.lock().unwrap() = Some(cover(&conn, root, window)?);
+                let root_geometry = conn.get_geometry(root)?.reply()?;
+                Ok(Rect::new(0.0, 0.0, root_geometry.width as f32, root_geometry.height as f32))
+            }
+        }
+    });
+    result.map_err(|e| eprintln!("Warning: Could not draw into the screensaver window: {}", e)).ok()
+}
+
+/// Moves the desk clock window to `geometry` (if given) and sets its decorations and stacking.
+pub fn set_desk_window(geometry: Option<WindowGeometry>, borderless: bool, always_on_top: bool) {
+    let result = x11rb::connect(None).map_err(Error::from).and_then(|(conn, screen_num)| {
+        let root = conn.setup().roots[screen_num].root;
+        let window = find_window(&conn, root, crate::WM_CLASS)?.ok_or("window not found")?;
+        set_desk(&conn, root, window, geometry, borderless, always_on_top)
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: Could not set up the desk window: {}", e);
+    }
+}
+
+/// Where our window is now, with the window manager's frame.
+pub fn window_geometry() -> Option<WindowGeometry> {
+    let result = x11rb::connect(None).map_err(Error::from).and_then(|(conn, screen_num)| {
+        let root = conn.setup().roots[screen_num].root;
+        let window = find_window(&conn, root, crate::WM_CLASS)?.ok_or("window not found")?;
+        framed_geometry(&conn, root, window)
+    });
+    result.map_err(|e| eprintln!("Warning: Could not read the window position: {}", e)).ok()
+}
+
+fn set_desk(
+    conn: &impl Connection,
+    root: Window,
+    window: Window,
+    geometry: Option<WindowGeometry>,
+    borderless: bool,
+    always_on_top: bool,
+) -> Result<(), Error> {
+    // Motif hints: flags (2 = decorations given), functions, decorations, input mode, status
+    let motif_hints = atom(conn, "_MOTIF_WM_HINTS")?;
+    conn.change_property32(PropMode::REPLACE, window, motif_hints, motif_hints, &[2, 0, u32::from(!borderless), 0, 0])?;
+
+    let wm_state = atom(conn, "_NET_WM_STATE")?;
+    let above = atom(conn, "_NET_WM_STATE_ABOVE")?;
+    send_wm_message(conn, root, window, wm_state, [u32::from(always_on_top), above, 0, 1, 0])?;
+
+    if let Some(g) = geometry {
+        // Window managers place the frame at x/y, as `framed_geometry` reports it
+        conn.configure_window(window, &ConfigureWindowAux::new().x(g.x).y(g.y).width(g.width).height(g.height))?;
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+fn framed_geometry(conn: &impl Connection, root: Window, window: Window) -> Result<WindowGeometry, Error> {
+    let geometry = conn.get_geometry(window)?.reply()?;
+    let origin = conn.translate_coordinates(window, root, 0, 0)?.reply()?;
+    // Left, right, top and bottom; absent without a window manager or when borderless
+    let extents = conn.get_property(false, window, atom(conn, "_NET_FRAME_EXTENTS")?, AtomEnum::CARDINAL, 0, 4)?.reply()?;
+    let extents: Vec<u32> = extents.value32().map(|v| v.collect()).unwrap_or_default();
+    let (left, top) = match extents[..] {
+        [left, _, top, _] => (left as i32, top as i32),
+        _ => (0, 0),
+    };
+    Ok(WindowGeometry {
+        x: origin.dst_x as i32 - left,
+        y: origin.dst_y as i32 - top,
+        width: geometry.width as u32,
+        height: geometry.height as u32,
+    })
+}
+
+fn atom(conn: &impl Connection, name: &str) -> Result<Atom, Error> {
+    Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+}
+
+/// Finds the top-level window with `WM_CLASS` `class`, looking inside window manager frames.
+fn find_window(conn: &impl Connection, parent: Window, class: &str) -> Result<Option<Window>, Error> {
+    let children = conn.query_tree(parent)?.reply()?.children;
+    for &child in &children {
+        let wm_class = conn.get_property(false, child, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 256)?.reply()?;
+        // Instance and class name, each nul-terminated
+        if wm_class.value.split(|b| *b == 0).any(|name| name == class.as_bytes()) {
+            return Ok(Some(child));
+        }
+    }
+    for &child in &children {
+        if let Some(window) = find_window(conn, child, class)? {
+            return Ok(Some(window));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the running window manager advertises `feature` in `_NET_SUPPORTED`.
+fn wm_supports(conn: &impl Connection, root: Window, feature: Atom) -> Result<bool, Error> {
+    let check = conn.get_property(false, root, atom(conn, "_NET_SUPPORTING_WM_CHECK")?, AtomEnum::WINDOW, 0, 1)?.reply()?;
+    if check.value32().and_then(|mut v| v.next()).is_none() {
+        return Ok(false);
+    }
+    let supported = conn.get_property(false, root, atom(conn, "_NET_SUPPORTED")?, AtomEnum::ATOM, 0, u32::MAX)?.reply()?;
+    Ok(supported.value32().is_some_and(|mut atoms| atoms.any(|a| a == feature)))
+}
+
+fn send_wm_message(conn: &impl Connection, root: Window, window: Window, message: Atom, data: [u32; 5]) -> Result<(), Error> {
+    let event = ClientMessageEvent::new(32, window, message, data);
+    conn.send_event(false, root, EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY, event)?;
+    Ok(())
+}
+
+fn cover(conn: &impl Connection, root: Window, window: Window) -> Result<SavedWindow, Error> {
+    let geometry = conn.get_geometry(window)?.reply()?;
+    let origin = conn.translate_coordinates(window, root, 0, 0)?.reply()?;
+    let root_geometry = conn.get_geometry(root)?.reply()?;
+    let mut saved = SavedWindow {
+        window,
+        x: origin.dst_x as i32,
+        y: origin.dst_y as i32,
+        width: geometry.width as u32,
+        height: geometry.height as u32,
+        override_redirect: false,
+    };
+
+    let fullscreen_monitors = atom(conn, "_NET_WM_FULLSCREEN_MONITORS")?;
+    let edges = xinerama_edges(conn)?;
+    match edges {
+        Some([top, bottom, left, right]) if wm_supports(conn, root, fullscreen_monitors)? => {
+            // Source indication 1 = a normal application
+            send_wm_message(conn, root, window, fullscreen_monitors, [top, bottom, left, right, 1])?;
+            let wm_state = atom(conn, "_NET_WM_STATE")?;
+            let fullscreen = atom(conn, "_NET_WM_STATE_FULLSCREEN")?;
+            send_wm_message(conn, root, window, wm_state, [1, fullscreen, 0, 1, 0])?;
+        }
+        _ => {
+            saved.override_redirect = true;
+            conn.unmap_window(window)?;
+            conn.change_window_attributes(window, &ChangeWindowAttributesAux::new().override_redirect(1))?;
+            conn.configure_window(window, &ConfigureWindowAux::new()
+                .x(0)
+                .y(0)
+                .width(root_geometry.width as u32)
+                .height(root_geometry.height as u32)
+                .border_width(0)
+                .stack_mode(StackMode::ABOVE))?;
+            conn.map_window(window)?;
+            // Nothing hands focus to an override-redirect window, and we want the exit keys
+            conn.sync()?;
+            conn.set_input_focus(InputFocus::PARENT, window, CURRENT_TIME)?;
+        }
+    }
+    conn.flush()?;
+    Ok(saved)
+}
+
+fn restore(conn: &impl Connection, root: Window, saved: SavedWindow) -> Result<(), Error> {
+    let window = saved.window;
+    if saved.override_redirect {
+        conn.unmap_window(window)?;
+        conn.change_window_attributes(window, &ChangeWindowAttributesAux::new().override_redirect(0))?;
+    } else {
+        let wm_state = atom(conn, "_NET_WM_STATE")?;
+        let fullscreen = atom(conn, "_NET_WM_STATE_FULLSCREEN")?;
+        send_wm_message(conn, root, window, wm_state, [0, fullscreen, 0, 1, 0])?;
+    }
+    conn.configure_window(window, &ConfigureWindowAux::new()
+        .x(saved.x)
+        .y(saved.y)
+        .width(saved.width)
+        .height(saved.height))?;
+    if saved.override_redirect {
+        conn.map_window(window)?;
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+fn reparent(conn: &impl Connection, root: Window, window: Window, parent: Window) -> Result<Rect, Error> {
+    let geometry = conn.get_geometry(parent)?.reply()?;
+    let origin = conn.translate_coordinates(parent, root, 0, 0)?.reply()?;
+
+    // Out of the window manager's hands for good
+    conn.unmap_window(window)?;
+    conn.change_window_attributes(window, &ChangeWindowAttributesAux::new().override_redirect(1))?;
+    conn.reparent_window(window, parent, 0, 0)?;
+    conn.configure_window(window, &ConfigureWindowAux::new()
+        .x(0)
+        .y(0)
+        .width(geometry.width as u32)
+        .height(geometry.height as u32)
+        .border_width(0))?;
+    conn.map_window(window)?;
+    conn.flush()?;
+    Ok(Rect::new(origin.dst_x as f32, origin.dst_y as f32, geometry.width as f32, geometry.height as f32))
+}
+
+/// Xinerama indices of the top-, bottom-, left- and right-most monitors,
+/// as `_NET_WM_FULLSCREEN_MONITORS` wants them.
+fn xinerama_edges(conn: &impl Connection) -> Result<Option<[u32; 4]>, Error> {
+    if conn.xinerama_is_active()?.reply()?.state == 0 {
+        return Ok(None);
+    }
+    let screens = conn.xinerama_query_screens()?.reply()?.screen_info;
+    let rects: Vec<(i32, i32, i32, i32)> = screens.iter()
+        .map(|s| (s.x_org as i32, s.y_org as i32, s.width as i32, s.height as i32))
+        .collect();
+    Ok(edge_monitors(&rects))
+}
+
+fn edge_monitors(rects: &[(i32, i32, i32, i32)]) -> Option<[u32; 4]> {
+    let index_by = |key: fn(&(i32, i32, i32, i32)) -> i32, max: bool| {
+        let found = if max {
+            rects.iter().enumerate().max_by_key(|(_, r)| key(r))
+        } else {
+            rects.iter().enumerate().min_by_key(|(_, r)| key(r))
+        };
+        found.map(|(i, _)| i as u32)
+    };
+    Some([
+        index_by(|r| r.1, false)?,
+        index_by(|r| r.1 + r.3, true)?,
+        index_by(|r| r.0, false)?,
+        index_by(|r| r.0 + r.2, true)?,
+    ])
+}
+
+/// Many setups never set a primary output; pick the one at the origin, else the first.
+fn ensure_primary(monitors: &mut [MonitorInfo]) {
+    if monitors.iter().any(|m| m.is_primary) {
+        return;
+    }
+    let index = monitors.iter().position(|m| m.x == 0 && m.y == 0).unwrap_or(0);
+    if let Some(m) = monitors.get_mut(index) {
+        m.is_primary = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(name: &str, x: i32, y: i32) -> MonitorInfo {
+        MonitorInfo { name: name.to_string(), x, y, width: 1920, height: 1080, is_primary: false, edid: None }
+    }
+
+    #[test]
+    fn test_ensure_primary() {
+        let mut monitors = vec![monitor("HDMI-1", -1920, 0), monitor("DP-1", 0, 0)];
+        ensure_primary(&mut monitors);
+        assert!(!monitors[0].is_primary);
+        assert!(monitors[1].is_primary);
+
+        // An explicit primary is left alone
+        monitors[1].is_primary = false;
+        monitors[0].is_primary = true;
+        ensure_primary(&mut monitors);
+        assert!(monitors[0].is_primary && !monitors[1].is_primary);
+    }
+
+    #[test]
+    fn test_edge_monitors() {
+        // Laptop below-left of two side-by-side screens
+        let rects = [(0, 0, 1920, 1080), (1920, 0, 1920, 1080), (0, 1080, 1366, 768)];
+        assert_eq!(edge_monitors(&rects), Some([0, 2, 0, 1]));
+        assert_eq!(edge_monitors(&[]), None);
+    }
+
+    /// Run with `cargo test -- --ignored` against Xvfb (no window manager, so the
+    /// override-redirect path) or a real desktop.
+    #[test]
+    #[ignore = "needs an X server"]
+    fn test_cover_and_restore_window() {
+        use x11rb::protocol::xproto::{CreateWindowAux, WindowClass};
+
+        let (conn, screen_num) = x11rb::connect(None).expect("no X server on $DISPLAY");
+        let screen = &conn.setup().roots[screen_num];
+        let window = conn.generate_id().unwrap();
+        conn.create_window(0, window, screen.root, 40, 30, 640, 480, 0, WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new()).unwrap();
+        conn.change_property8(x11rb::protocol::xproto::PropMode::REPLACE, window, AtomEnum::WM_CLASS, AtomEnum::STRING, b"flip_test\0flip_test\0").unwrap();
+        conn.map_window(window).unwrap();
+        conn.sync().unwrap();
+        assert_eq!(find_window(&conn, screen.root, "flip_test").unwrap(), Some(window));
+
+        let saved = cover(&conn, screen.root, window).unwrap();
+        conn.sync().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let covered = conn.get_geometry(window).unwrap().reply().unwrap();
+        assert_eq!((covered.width, covered.height), (screen.width_in_pixels, screen.height_in_pixels));
+
+        restore(&conn, screen.root, saved).unwrap();
+        conn.sync().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let restored = conn.get_geometry(window).unwrap().reply().unwrap();
+        assert_eq!((restored.width, restored.height), (640, 480));
+    }
+
+    #[test]
+    #[ignore = "needs an X server"]
+    fn test_reparent_into_host_window() {
+        use x11rb::protocol::xproto::{CreateWindowAux, WindowClass};
+
+        let (conn, screen_num) = x11rb::connect(None).expect("no X server on $DISPLAY");
+        let screen = &conn.setup().roots[screen_num];
+        let create = |x, y, w, h| {
+            let id = conn.generate_id().unwrap();
+            conn.create_window(0, id, screen.root, x, y, w, h, 0, WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new()).unwrap();
+            conn.map_window(id).unwrap();
+            id
+        };
+        let host = create(100, 50, 800, 600);
+        let window = create(0, 0, 320, 200);
+        conn.sync().unwrap();
+
+        let area = reparent(&conn, screen.root, window, host).unwrap();
+        assert_eq!(area, Rect::new(100.0, 50.0, 800.0, 600.0));
+        assert_eq!(conn.query_tree(window).unwrap().reply().unwrap().parent, host);
+        let geometry = conn.get_geometry(window).unwrap().reply().unwrap();
+        assert_eq!((geometry.width, geometry.height), (800, 600));
+    }
+
+    /// Run with `cargo test -- --ignored` against a real display, or Xvfb split into
+    /// several monitors with `xrandr --setmonitor`.
+    #[test]
+    #[ignore = "needs an X server"]
+    fn test_monitors_from_x_server() {
+        let monitors = get_monitors().expect("no X server on $DISPLAY");
+        assert!(!monitors.is_empty());
+        assert_eq!(monitors.iter().filter(|m| m.is_primary).count(), 1);
+        assert!(monitors.iter().all(|m| m.width > 0 && m.height > 0 && !m.name.is_empty()));
+
+        let mut names: Vec<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), monitors.len());
+    }
+}